@@ -0,0 +1,96 @@
+//! Compares the cost of writes to large buckets for the two bucket implementations.
+//!
+//! Run once with the default std-backed buckets and once with persistent buckets:
+//!
+//! ```text
+//! cargo bench --bench buckets
+//! cargo bench --bench buckets --features persistent
+//! ```
+
+use std::time::{Duration, Instant};
+
+use stm::atomically;
+use stm_datastructures::{THashMap, THashSet};
+
+/// Number of buckets used for every run. Kept small so that buckets grow large.
+const BUCKETS: usize = 4;
+
+/// Number of timed operations per run.
+const OPERATIONS: usize = 10_000;
+
+fn report(name: &str, bucket_len: usize, elapsed: Duration) {
+    println!(
+        "{:<24} {:>8} elements/bucket {:>10.2} µs/op",
+        name,
+        bucket_len,
+        elapsed.as_secs_f64() * 1e6 / OPERATIONS as f64
+    );
+}
+
+fn bench_map_update(bucket_len: usize) {
+    let map = THashMap::new(BUCKETS);
+    atomically(|trans| {
+        for i in 0..bucket_len * BUCKETS {
+            map.insert(trans, i, 0u64)?;
+        }
+        Ok(())
+    });
+
+    let start = Instant::now();
+    for i in 0..OPERATIONS {
+        let key = i % (bucket_len * BUCKETS);
+        atomically(|trans| map.entry(trans, key)?.and_modify(|v| *v += 1).map(|_| ()));
+    }
+    report("THashMap update", bucket_len, start.elapsed());
+}
+
+fn bench_map_lookup(bucket_len: usize) {
+    let map = THashMap::new(BUCKETS);
+    atomically(|trans| {
+        for i in 0..bucket_len * BUCKETS {
+            map.insert(trans, i, 0u64)?;
+        }
+        Ok(())
+    });
+
+    let start = Instant::now();
+    for i in 0..OPERATIONS {
+        let key = i % (bucket_len * BUCKETS);
+        atomically(|trans| map.get(trans, &key));
+    }
+    report("THashMap lookup", bucket_len, start.elapsed());
+}
+
+fn bench_set_insert_remove(bucket_len: usize) {
+    let set = THashSet::new(BUCKETS);
+    atomically(|trans| {
+        for i in 0..bucket_len * BUCKETS {
+            set.insert(trans, i)?;
+        }
+        Ok(())
+    });
+
+    let start = Instant::now();
+    for i in 0..OPERATIONS {
+        let value = usize::MAX - i;
+        atomically(|trans| {
+            set.insert(trans, value)?;
+            set.remove(trans, &value)
+        });
+    }
+    report("THashSet insert+remove", bucket_len, start.elapsed());
+}
+
+fn main() {
+    if cfg!(feature = "persistent") {
+        println!("bucket storage: persistent (im)");
+    } else {
+        println!("bucket storage: std");
+    }
+
+    for &bucket_len in &[16, 256, 4096, 65536] {
+        bench_map_update(bucket_len);
+        bench_map_lookup(bucket_len);
+        bench_set_insert_remove(bucket_len);
+    }
+}
@@ -0,0 +1,223 @@
+use std::any::Any;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::iter;
+use stm::TVar;
+
+use crate::storage::{MapBucket, MapStorage, SetBucket, SetStorage};
+use crate::{
+    bucket_index, default_bucket_count, ConfigError, ResizePolicy, Shard, Sharded, TFineHashMap, THashMap,
+    THashSet,
+};
+
+/// Configuration for `THashSet`, `THashMap`, `TFineHashMap` and custom `Sharded` containers.
+///
+/// ```
+/// use stm_datastructures::{Builder, FixedState, THashMap};
+///
+/// let map: THashMap<u64, String, FixedState> = Builder::new()
+///     .buckets(128)
+///     .capacity(10_000)
+///     .hasher(FixedState::with_seed(1))
+///     .build_map()
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct Builder<S = RandomState> {
+    bucket_count: Option<usize>,
+    capacity: usize,
+    hash_builder: S,
+    resize_policy: Option<ResizePolicy>,
+    counter_shards: Option<usize>,
+}
+
+impl Builder<RandomState> {
+    /// Creates a builder with the default bucket count, no preallocated capacity and a
+    /// `RandomState` hasher.
+    pub fn new() -> Self {
+        Builder {
+            bucket_count: None,
+            capacity: 0,
+            hash_builder: RandomState::new(),
+            resize_policy: None,
+            counter_shards: None,
+        }
+    }
+}
+
+impl Default for Builder<RandomState> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Builder<S>
+where
+    S: BuildHasher,
+{
+    /// Sets the number of buckets. Defaults to a multiple of the available parallelism.
+    pub fn buckets(mut self, bucket_count: usize) -> Self {
+        self.bucket_count = Some(bucket_count);
+        self
+    }
+
+    /// Sets the number of elements the container can hold in total before any bucket has to
+    /// reallocate. The capacity is split evenly between the buckets.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Lets the container grow and shrink its bucket array according to the given policy. By
+    /// default, the bucket count only changes through explicit calls to `resize`.
+    pub fn resize_policy(mut self, policy: ResizePolicy) -> Self {
+        self.resize_policy = Some(policy);
+        self
+    }
+
+    /// Keeps track of the number of elements in `shards` counters, so that `len` reads these
+    /// counters instead of every bucket. Without this option, `len` reads every bucket.
+    ///
+    /// Every insertion and removal then also writes one of the counters. Threads are spread over
+    /// the shards, but writers sharing a shard conflict even if they touch different buckets, so
+    /// this only pays off if `len` is called frequently. One shard per hardware thread is a good
+    /// starting point. Resize policies benefit as well, since their load factor checks use `len`.
+    pub fn len_counter(mut self, shards: usize) -> Self {
+        self.counter_shards = Some(shards);
+        self
+    }
+
+    /// Sets the hash builder used to distribute elements over the buckets.
+    pub fn hasher<S2>(self, hash_builder: S2) -> Builder<S2>
+    where
+        S2: BuildHasher,
+    {
+        Builder {
+            bucket_count: self.bucket_count,
+            capacity: self.capacity,
+            hash_builder,
+            resize_policy: self.resize_policy,
+            counter_shards: self.counter_shards,
+        }
+    }
+
+    /// Builds an empty `THashSet` with this configuration.
+    pub fn build_set<T>(self) -> Result<THashSet<T, S>, ConfigError>
+    where
+        T: Any + Clone + Eq + Hash + Send + Sync,
+    {
+        self.build_set_from(iter::empty())
+    }
+
+    /// Builds a `THashSet` with this configuration that holds the given values.
+    ///
+    /// The values are placed in their buckets directly, without any transaction. The capacity is
+    /// raised to the lower size bound of `values` if necessary.
+    pub fn build_set_from<T, I>(self, values: I) -> Result<THashSet<T, S>, ConfigError>
+    where
+        T: Any + Clone + Eq + Hash + Send + Sync,
+        I: IntoIterator<Item = T>,
+    {
+        let values = values.into_iter();
+        let (bucket_count, bucket_capacity) = self.layout(values.size_hint().0)?;
+        let mut buckets: Vec<SetBucket<T>> = (0..bucket_count)
+            .map(|_| SetStorage::with_capacity(bucket_capacity))
+            .collect();
+
+        for value in values {
+            buckets[bucket_index(&self.hash_builder, &value, bucket_count)].insert_value(value);
+        }
+
+        Ok(THashSet::from_buckets(buckets, self.hash_builder, self.resize_policy, self.counter_shards))
+    }
+
+    /// Builds an empty `THashMap` with this configuration.
+    pub fn build_map<K, V>(self) -> Result<THashMap<K, V, S>, ConfigError>
+    where
+        K: Any + Clone + Eq + Hash + Send + Sync,
+        V: Any + Clone + Send + Sync,
+    {
+        self.build_map_from(iter::empty())
+    }
+
+    /// Builds a `THashMap` with this configuration that holds the given entries, e.g. the
+    /// contents of a `HashMap`. If a key occurs more than once, the last value wins.
+    ///
+    /// The entries are placed in their buckets directly, without any transaction. The capacity is
+    /// raised to the lower size bound of `entries` if necessary.
+    pub fn build_map_from<K, V, I>(self, entries: I) -> Result<THashMap<K, V, S>, ConfigError>
+    where
+        K: Any + Clone + Eq + Hash + Send + Sync,
+        V: Any + Clone + Send + Sync,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let entries = entries.into_iter();
+        let (bucket_count, bucket_capacity) = self.layout(entries.size_hint().0)?;
+        let mut buckets: Vec<MapBucket<K, V>> = (0..bucket_count)
+            .map(|_| MapStorage::with_capacity(bucket_capacity))
+            .collect();
+
+        for (k, v) in entries {
+            buckets[bucket_index(&self.hash_builder, &k, bucket_count)].insert(k, v);
+        }
+
+        Ok(THashMap::from_buckets(buckets, self.hash_builder, self.resize_policy, self.counter_shards))
+    }
+
+    /// Builds an empty `TFineHashMap` with this configuration.
+    pub fn build_fine_map<K, V>(self) -> Result<TFineHashMap<K, V, S>, ConfigError>
+    where
+        K: Any + Clone + Eq + Hash + Send + Sync,
+        V: Any + Clone + Send + Sync,
+    {
+        let (bucket_count, bucket_capacity) = self.layout(0)?;
+        let buckets: Vec<MapBucket<K, TVar<V>>> = (0..bucket_count)
+            .map(|_| MapStorage::with_capacity(bucket_capacity))
+            .collect();
+
+        Ok(TFineHashMap::from_buckets(buckets, self.hash_builder, self.resize_policy, self.counter_shards))
+    }
+
+    /// Builds an empty `Sharded` bucket array with this configuration, as a basis for custom
+    /// containers. The capacity is ignored, as `Shard` has no notion of it.
+    pub fn build_sharded<C>(self) -> Result<Sharded<C, S>, ConfigError>
+    where
+        C: Shard,
+    {
+        self.build_sharded_from(iter::empty())
+    }
+
+    /// Builds a `Sharded` bucket array with this configuration that holds the given elements.
+    pub fn build_sharded_from<C, I>(self, items: I) -> Result<Sharded<C, S>, ConfigError>
+    where
+        C: Shard,
+        I: IntoIterator<Item = C::Item>,
+    {
+        let (bucket_count, _) = self.layout(0)?;
+        let mut buckets: Vec<C> = (0..bucket_count).map(|_| C::default()).collect();
+
+        for item in items {
+            let idx = bucket_index(&self.hash_builder, C::key(&item), bucket_count);
+            buckets[idx].extend(Some(item));
+        }
+
+        Ok(Sharded::from_parts(buckets, self.hash_builder, self.resize_policy, self.counter_shards))
+    }
+
+    /// Validates the configuration and computes the capacity of each bucket.
+    fn layout(&self, min_capacity: usize) -> Result<(usize, usize), ConfigError> {
+        let bucket_count = self.bucket_count.unwrap_or_else(default_bucket_count);
+        if bucket_count == 0 {
+            return Err(ConfigError::ZeroBuckets);
+        }
+        if let Some(policy) = &self.resize_policy {
+            policy.validate()?;
+        }
+        if self.counter_shards == Some(0) {
+            return Err(ConfigError::ZeroCounterShards);
+        }
+
+        let capacity = self.capacity.max(min_capacity);
+        Ok((bucket_count, capacity.div_ceil(bucket_count)))
+    }
+}
@@ -0,0 +1,212 @@
+use std::any::Any;
+use std::hash::{BuildHasher, Hash};
+use stm::{atomically, StmResult, Transaction};
+
+use crate::sharded::{Shard, Sharded};
+use crate::storage::{MapBucket, SetBucket};
+
+/// The point at which a cursor continues its scan, obtained through `position`.
+///
+/// Besides the index of the next bucket, it records the bucket count the scan has seen so far,
+/// so that a scan resumed with `cursor_at` can tell whether the container was resized in between.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CursorPosition {
+    /// Index of the next bucket to be scanned.
+    pub index: usize,
+    /// Number of buckets when the last chunk was read, or `None` if no chunk has been read yet.
+    pub bucket_count: Option<usize>,
+}
+
+/// Progress of a scan over a bucket array, shared by all cursor types.
+#[derive(Clone, Copy, Debug)]
+struct ScanState {
+    position: CursorPosition,
+    buckets_per_chunk: usize,
+    layout_changed: bool,
+}
+
+impl ScanState {
+    fn new(position: CursorPosition, buckets_per_chunk: usize) -> Self {
+        ScanState {
+            position,
+            buckets_per_chunk: buckets_per_chunk.max(1),
+            layout_changed: false,
+        }
+    }
+
+    /// Reads the next chunk and passes it to `f` in the same transaction. The position only
+    /// advances once that transaction has committed.
+    fn next<C, S, R, F>(&mut self, buckets: &Sharded<C, S>, f: F) -> Option<R>
+    where
+        C: Shard,
+        S: BuildHasher,
+        F: Fn(&mut Transaction, Vec<C::Item>) -> StmResult<R>,
+    {
+        let start = self.position.index;
+        let (result, bucket_count) = atomically(|trans| {
+            let (items, bucket_count) = buckets.read_chunk(trans, start, self.buckets_per_chunk)?;
+            if start >= bucket_count {
+                return Ok((None, bucket_count));
+            }
+            f(trans, items).map(|result| (Some(result), bucket_count))
+        });
+
+        if self.position.bucket_count.is_some_and(|count| count != bucket_count) {
+            self.layout_changed = true;
+        }
+        self.position = CursorPosition {
+            index: start.saturating_add(self.buckets_per_chunk).min(bucket_count),
+            bucket_count: Some(bucket_count),
+        };
+
+        result
+    }
+}
+
+/// A resumable scan over a `THashMap` that reads a fixed number of buckets per transaction.
+///
+/// Scanning a large map in a single transaction creates a huge read set that rarely commits while
+/// other transactions write to the map. A cursor splits the scan into many small transactions,
+/// at the price of weaker guarantees:
+///
+/// - Every chunk is read consistently, but different chunks may reflect different states of the
+///   map.
+/// - Entries that are present during the whole scan are visited exactly once, as long as the map
+///   is not resized.
+/// - Entries inserted or removed during the scan may or may not be visited.
+/// - If the map is resized between two chunks, the scan continues at the same bucket index of the
+///   new layout, so entries may be missed or visited twice. `layout_changed` reports this, also
+///   when the resize happened before a scan was resumed from a saved position.
+///
+/// The cursor runs its own transactions, so it must not be used inside a transaction. Its
+/// `position` can be stored to resume the scan later with `THashMap::cursor_at`.
+pub struct MapCursor<'a, K, V, S> {
+    buckets: &'a Sharded<MapBucket<K, V>, S>,
+    state: ScanState,
+}
+
+impl<'a, K, V, S> MapCursor<'a, K, V, S>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+    S: BuildHasher,
+{
+    pub(crate) fn new(
+        buckets: &'a Sharded<MapBucket<K, V>, S>,
+        position: CursorPosition,
+        buckets_per_chunk: usize,
+    ) -> Self {
+        MapCursor {
+            buckets,
+            state: ScanState::new(position, buckets_per_chunk),
+        }
+    }
+
+    /// Returns copies of the entries of the next chunk of buckets, or `None` once all buckets
+    /// have been scanned.
+    pub fn next_chunk(&mut self) -> Option<Vec<(K, V)>> {
+        self.next_chunk_with(|_, entries| Ok(entries))
+    }
+
+    /// Reads the next chunk of buckets and passes the entries to `f` within the same
+    /// transaction, e.g. to remove or rewrite them. Returns `None` once all buckets have been
+    /// scanned.
+    ///
+    /// Like any transaction, `f` may be executed several times.
+    pub fn next_chunk_with<R, F>(&mut self, f: F) -> Option<R>
+    where
+        F: Fn(&mut Transaction, Vec<(K, V)>) -> StmResult<R>,
+    {
+        self.state.next(self.buckets, f)
+    }
+
+    /// Returns the point at which the scan continues.
+    pub fn position(&self) -> CursorPosition {
+        self.state.position
+    }
+
+    /// Returns `true` if the bucket count changed between two chunks of this scan, or since the
+    /// position the scan was resumed from.
+    pub fn layout_changed(&self) -> bool {
+        self.state.layout_changed
+    }
+}
+
+impl<'a, K, V, S> Iterator for MapCursor<'a, K, V, S>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+    S: BuildHasher,
+{
+    type Item = Vec<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_chunk()
+    }
+}
+
+/// A resumable scan over a `THashSet` that reads a fixed number of buckets per transaction.
+///
+/// The same weak consistency guarantees as for `MapCursor` apply. The `position` can be stored
+/// to resume the scan later with `THashSet::cursor_at`.
+pub struct SetCursor<'a, T, S> {
+    buckets: &'a Sharded<SetBucket<T>, S>,
+    state: ScanState,
+}
+
+impl<'a, T, S> SetCursor<'a, T, S>
+where
+    T: Any + Clone + Eq + Hash + Send + Sync,
+    S: BuildHasher,
+{
+    pub(crate) fn new(
+        buckets: &'a Sharded<SetBucket<T>, S>,
+        position: CursorPosition,
+        buckets_per_chunk: usize,
+    ) -> Self {
+        SetCursor {
+            buckets,
+            state: ScanState::new(position, buckets_per_chunk),
+        }
+    }
+
+    /// Returns copies of the values of the next chunk of buckets, or `None` once all buckets
+    /// have been scanned.
+    pub fn next_chunk(&mut self) -> Option<Vec<T>> {
+        self.next_chunk_with(|_, values| Ok(values))
+    }
+
+    /// Reads the next chunk of buckets and passes the values to `f` within the same transaction.
+    /// Returns `None` once all buckets have been scanned.
+    ///
+    /// Like any transaction, `f` may be executed several times.
+    pub fn next_chunk_with<R, F>(&mut self, f: F) -> Option<R>
+    where
+        F: Fn(&mut Transaction, Vec<T>) -> StmResult<R>,
+    {
+        self.state.next(self.buckets, f)
+    }
+
+    /// Returns the point at which the scan continues.
+    pub fn position(&self) -> CursorPosition {
+        self.state.position
+    }
+
+    /// Returns `true` if the bucket count changed between two chunks of this scan, or since the
+    /// position the scan was resumed from.
+    pub fn layout_changed(&self) -> bool {
+        self.state.layout_changed
+    }
+}
+
+impl<'a, T, S> Iterator for SetCursor<'a, T, S>
+where
+    T: Any + Clone + Eq + Hash + Send + Sync,
+    S: BuildHasher,
+{
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_chunk()
+    }
+}
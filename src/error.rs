@@ -0,0 +1,68 @@
+use std::error::Error;
+use std::fmt;
+
+/// Errors reported when constructing a container with an invalid configuration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The requested bucket count was zero. Every container needs at least one bucket.
+    ZeroBuckets,
+    /// The resize policy has a maximum load factor of zero or a minimum load factor that is not
+    /// below the maximum.
+    InvalidResizePolicy,
+    /// The element counter was configured with zero shards.
+    ZeroCounterShards,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::ZeroBuckets => write!(f, "the bucket count must be at least 1"),
+            ConfigError::InvalidResizePolicy => {
+                write!(f, "the resize policy needs 0 <= min_load < max_load")
+            }
+            ConfigError::ZeroCounterShards => {
+                write!(f, "the element counter needs at least 1 shard")
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Returned by `BucketView::insert` when the key belongs into a different bucket. Holds the
+/// rejected key and value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ForeignKeyError<K, V> {
+    /// The rejected key.
+    pub key: K,
+    /// The value that was to be inserted with the key.
+    pub value: V,
+}
+
+impl<K, V> ForeignKeyError<K, V> {
+    /// Returns the rejected key and value.
+    pub fn into_inner(self) -> (K, V) {
+        (self.key, self.value)
+    }
+}
+
+impl<K, V> fmt::Display for ForeignKeyError<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the key does not belong into this bucket")
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> Error for ForeignKeyError<K, V> {}
+
+/// Returned by `atomically_with_timeout` when the transaction kept retrying until the timeout
+/// expired.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timeout;
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the transaction did not complete before the timeout expired")
+    }
+}
+
+impl Error for Timeout {}
@@ -0,0 +1,298 @@
+use std::any::Any;
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use stm::{atomically, StmResult, TVar, Transaction};
+
+use crate::sharded::Sharded;
+use crate::storage::MapBucket;
+use crate::{default_bucket_count, Builder, ConfigError, ResizePolicy};
+
+/// A transaction-ready hash map which stores every value in its own `TVar`.
+///
+/// In a `THashMap`, every write clones the whole bucket and conflicts with all other writers to
+/// that bucket. Here, the buckets only map keys to value `TVar`s and are written when keys are
+/// inserted or removed. Updating the value of an existing key only writes the value's `TVar`, so
+/// transactions updating different keys of the same bucket do not conflict.
+///
+/// The price is an additional `TVar` per entry and an additional read for every lookup.
+///
+/// Cloning a `TFineHashMap` is cheap and returns a handle to the same map: the buckets live in
+/// `TVar`s, which are shared between clones, so a change made through one handle is visible
+/// through all others. `handle` does the same but makes the intent explicit, while `deep_clone`
+/// creates an independent copy.
+#[derive(Clone)]
+pub struct TFineHashMap<K, V, S = RandomState> {
+    buckets: Sharded<MapBucket<K, TVar<V>>, S>,
+}
+
+impl<K, V> TFineHashMap<K, V, RandomState>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+{
+    /// Creates a new map with the given number of buckets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket_count` is zero. See `try_new` for a fallible version.
+    pub fn new(bucket_count: usize) -> Self {
+        Self::with_buckets_and_hasher(bucket_count, RandomState::new())
+    }
+
+    /// Creates a new map with the given number of buckets.
+    ///
+    /// Returns an error if `bucket_count` is zero.
+    pub fn try_new(bucket_count: usize) -> Result<Self, ConfigError> {
+        Self::try_with_buckets_and_hasher(bucket_count, RandomState::new())
+    }
+}
+
+impl<K, V, S> TFineHashMap<K, V, S>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+    S: BuildHasher,
+{
+    /// Creates a new map with the default number of buckets which uses the given hash builder to
+    /// distribute keys over the buckets.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self::with_buckets_and_hasher(default_bucket_count(), hash_builder)
+    }
+
+    /// Creates a new map with the given number of buckets which uses the given hash builder to
+    /// distribute keys over the buckets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket_count` is zero. See `try_with_buckets_and_hasher` for a fallible version.
+    pub fn with_buckets_and_hasher(bucket_count: usize, hash_builder: S) -> Self {
+        Self::try_with_buckets_and_hasher(bucket_count, hash_builder)
+            .unwrap_or_else(|e| panic!("cannot create TFineHashMap: {}", e))
+    }
+
+    /// Creates a new map with the given number of buckets which uses the given hash builder to
+    /// distribute keys over the buckets.
+    ///
+    /// Returns an error if `bucket_count` is zero.
+    pub fn try_with_buckets_and_hasher(bucket_count: usize, hash_builder: S) -> Result<Self, ConfigError> {
+        Builder::new()
+            .buckets(bucket_count)
+            .hasher(hash_builder)
+            .build_fine_map()
+    }
+
+    /// Creates a map from pre-populated buckets. The caller is responsible for placing every key
+    /// in the bucket `bucket_index` assigns to it.
+    pub(crate) fn from_buckets(
+        buckets: Vec<MapBucket<K, TVar<V>>>,
+        hash_builder: S,
+        policy: Option<ResizePolicy>,
+        counter_shards: Option<usize>,
+    ) -> Self {
+        TFineHashMap {
+            buckets: Sharded::from_parts(buckets, hash_builder, policy, counter_shards),
+        }
+    }
+
+    /// Returns another handle to this map. Equivalent to `clone`.
+    pub fn handle(&self) -> Self
+    where
+        S: Clone,
+    {
+        self.clone()
+    }
+
+    /// Creates an independent map with the same contents, bucket count, hasher and resize
+    /// policy. Every value is copied into a new `TVar`.
+    ///
+    /// Every bucket is part of the read set of the transaction.
+    pub fn deep_clone(&self, trans: &mut Transaction) -> StmResult<Self>
+    where
+        S: Clone,
+    {
+        let buckets = self.buckets.deep_clone(trans, |trans, bucket| {
+            bucket
+                .into_iter()
+                .map(|(k, v)| Ok((k, TVar::new(v.read(trans)?))))
+                .collect()
+        })?;
+        Ok(TFineHashMap { buckets })
+    }
+
+    /// Returns a reference to the map's `BuildHasher`.
+    pub fn hasher(&self) -> &S {
+        self.buckets.hasher()
+    }
+
+    /// Returns the current number of buckets.
+    pub fn bucket_count(&self, trans: &mut Transaction) -> StmResult<usize> {
+        self.buckets.bucket_count(trans)
+    }
+
+    /// Redistributes all keys over `bucket_count` buckets. The value `TVar`s are kept.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket_count` is zero.
+    pub fn resize(&self, trans: &mut Transaction, bucket_count: usize) -> StmResult<()> {
+        self.buckets.resize(trans, bucket_count)
+    }
+
+    /// Returns the `TVar` holding the value of the given key, if the key is present.
+    fn value_var<Q>(&self, trans: &mut Transaction, key: &Q) -> StmResult<Option<TVar<V>>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let map = self.buckets.bucket_for(trans, key)?.read(trans)?;
+        Ok(map.get(key).cloned())
+    }
+
+    /// Inserts a key-value pair into the map.
+    ///
+    /// If the map did not have this key present, `None` is returned and the bucket is written.
+    /// Otherwise only the value's `TVar` is updated and the old value is returned.
+    pub fn insert(&self, trans: &mut Transaction, key: K, value: V) -> StmResult<Option<V>> {
+        let bucket = self.buckets.bucket_for(trans, &key)?;
+        let mut map = bucket.read(trans)?;
+
+        if let Some(var) = map.get(&key) {
+            return var.replace(trans, value).map(Some);
+        }
+
+        map.insert(key, TVar::new(value));
+        let len = map.len();
+        self.buckets.store(trans, &bucket, len - 1, map)?;
+        self.buckets.inserted(trans, len)?;
+
+        Ok(None)
+    }
+
+    /// Returns a copy of the value corresponding to the key.
+    pub fn get<Q>(&self, trans: &mut Transaction, key: &Q) -> StmResult<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        match self.value_var(trans, key)? {
+            Some(var) => var.read(trans).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns `true` if the map contains a value for the specified key. Only the bucket is read.
+    pub fn contains_key<Q>(&self, trans: &mut Transaction, key: &Q) -> StmResult<bool>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        Ok(self.value_var(trans, key)?.is_some())
+    }
+
+    /// Applies `f` to the value of the given key. Returns `false` if the key is not present.
+    ///
+    /// Only the value's `TVar` is written.
+    pub fn modify<Q, F>(&self, trans: &mut Transaction, key: &Q, f: F) -> StmResult<bool>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        F: FnOnce(V) -> V,
+    {
+        match self.value_var(trans, key)? {
+            Some(var) => var.modify(trans, f).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Applies `f` to the value of the given key or inserts `default` if the key is not present.
+    /// Returns a copy of the resulting value.
+    ///
+    /// This is the typical operation for counters: only the first update of a key writes the
+    /// bucket.
+    pub fn modify_or_insert<F>(&self, trans: &mut Transaction, key: K, f: F, default: V) -> StmResult<V>
+    where
+        F: FnOnce(V) -> V,
+    {
+        let bucket = self.buckets.bucket_for(trans, &key)?;
+        let mut map = bucket.read(trans)?;
+
+        if let Some(var) = map.get(&key) {
+            let value = f(var.read(trans)?);
+            var.write(trans, value.clone())?;
+            return Ok(value);
+        }
+
+        map.insert(key, TVar::new(default.clone()));
+        let len = map.len();
+        self.buckets.store(trans, &bucket, len - 1, map)?;
+        self.buckets.inserted(trans, len)?;
+
+        Ok(default)
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key was previously in
+    /// the map.
+    pub fn remove<Q>(&self, trans: &mut Transaction, key: &Q) -> StmResult<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let bucket = self.buckets.bucket_for(trans, key)?;
+        let mut map = bucket.read(trans)?;
+
+        match map.remove(key) {
+            Some(var) => {
+                let len = map.len();
+                self.buckets.store(trans, &bucket, len + 1, map)?;
+                self.buckets.removed(trans, len)?;
+                var.read(trans).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the number of entries. Reads every bucket, but none of the values, unless the map
+    /// was built with `Builder::len_counter`.
+    pub fn len(&self, trans: &mut Transaction) -> StmResult<usize> {
+        self.buckets.len(trans)
+    }
+
+    /// Returns `true` if the map contains no entries.
+    pub fn is_empty(&self, trans: &mut Transaction) -> StmResult<bool> {
+        self.buckets.is_empty(trans)
+    }
+
+    /// Returns a copy of the whole map as read inside the given transaction.
+    ///
+    /// All buckets and values are part of the read set.
+    pub fn snapshot(&self, trans: &mut Transaction) -> StmResult<HashMap<K, V>> {
+        let mut result = HashMap::new();
+
+        for bucket in self.buckets.read(trans)?.iter() {
+            for (k, var) in bucket.read(trans)? {
+                result.insert(k, var.read(trans)?);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Returns a consistent copy of the whole map. Must not be called inside a transaction.
+    pub fn get_contents(&self) -> HashMap<K, V> {
+        atomically(|trans| self.snapshot(trans))
+    }
+}
+
+impl<K, V, S> Default for TFineHashMap<K, V, S>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+    S: BuildHasher + Default,
+{
+    /// Creates an empty map with the default number of buckets.
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
@@ -0,0 +1,34 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{BuildHasher, Hasher};
+
+/// A `BuildHasher` with a fixed, user-provided seed.
+///
+/// In contrast to `RandomState`, the distribution of values over buckets is the same in every run
+/// of the program, which makes experiments reproducible. As the seed is predictable, this should
+/// not be used with untrusted input.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FixedState {
+    seed: u64,
+}
+
+impl FixedState {
+    /// Creates a new hash builder using the given seed.
+    pub fn with_seed(seed: u64) -> Self {
+        FixedState { seed }
+    }
+
+    /// Returns the seed of this hash builder.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+impl BuildHasher for FixedState {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> DefaultHasher {
+        let mut hasher = DefaultHasher::new();
+        hasher.write_u64(self.seed);
+        hasher
+    }
+}
@@ -0,0 +1,1284 @@
+use std::any::Any;
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::iter::FromIterator;
+use std::mem;
+use std::sync::Arc;
+use stm::{atomically, retry, StmResult, TVar, Transaction};
+
+use crate::cursor::{CursorPosition, MapCursor};
+use crate::sharded::{BucketIter, Sharded};
+use crate::storage::{MapBucket, MapStorage};
+use crate::{bucket_index, default_bucket_count, Builder, ConfigError, ForeignKeyError, ResizePolicy};
+
+/// A transaction-ready hash map with a configurable number of buckets
+///
+/// Keys are distributed over the buckets using the `BuildHasher` `S`, which defaults to the
+/// randomly seeded `RandomState` of the standard library. The bucket count can be changed with
+/// `resize` or automatically by configuring a `ResizePolicy` through the `Builder`.
+///
+/// Cloning a `THashMap` is cheap and returns a handle to the same map: the buckets live in
+/// `TVar`s, which are shared between clones, so a change made through one handle is visible
+/// through all others. `handle` does the same but makes the intent explicit, while `deep_clone`
+/// creates an independent copy.
+#[derive(Clone)]
+pub struct THashMap<K, V, S = RandomState> {
+    buckets: Sharded<MapBucket<K, V>, S>,
+}
+
+impl<K, V> THashMap<K, V, RandomState> where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync
+{
+    /// Creates a new transaction-ready HashMap with the given number of buckets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket_count` is zero. See `try_new` for a fallible version.
+    pub fn new(bucket_count: usize) -> Self {
+        Self::with_buckets_and_hasher(bucket_count, RandomState::new())
+    }
+
+    /// Creates a new transaction-ready HashMap with the given number of buckets.
+    ///
+    /// Returns an error if `bucket_count` is zero.
+    pub fn try_new(bucket_count: usize) -> Result<Self, ConfigError> {
+        Self::try_with_buckets_and_hasher(bucket_count, RandomState::new())
+    }
+
+    /// Shorthand for more efficient population of a HashMap with data
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket_count` is zero. Use `Builder::build_map_from` for a fallible version.
+    pub fn from_hashmap(map: HashMap<K, V>, bucket_count: usize) -> Self {
+        Self::from_hashmap_with_hasher(map, bucket_count, RandomState::new())
+    }
+}
+
+impl<K, V, S> THashMap<K, V, S> where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+    S: BuildHasher,
+{
+    /// Creates a new transaction-ready HashMap with the default number of buckets which uses the
+    /// given hash builder to distribute keys over the buckets.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self::with_buckets_and_hasher(default_bucket_count(), hash_builder)
+    }
+
+    /// Creates a new transaction-ready HashMap with the given number of buckets which uses the
+    /// given hash builder to distribute keys over the buckets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket_count` is zero. See `try_with_buckets_and_hasher` for a fallible version.
+    pub fn with_buckets_and_hasher(bucket_count: usize, hash_builder: S) -> Self {
+        Self::try_with_buckets_and_hasher(bucket_count, hash_builder)
+            .unwrap_or_else(|e| panic!("cannot create THashMap: {}", e))
+    }
+
+    /// Creates a new transaction-ready HashMap with the given number of buckets which uses the
+    /// given hash builder to distribute keys over the buckets.
+    ///
+    /// Returns an error if `bucket_count` is zero.
+    pub fn try_with_buckets_and_hasher(bucket_count: usize, hash_builder: S) -> Result<Self, ConfigError> {
+        Builder::new()
+            .buckets(bucket_count)
+            .hasher(hash_builder)
+            .build_map()
+    }
+
+    /// Like `from_hashmap`, but uses the given hash builder to distribute keys over the buckets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket_count` is zero. Use `Builder::build_map_from` for a fallible version.
+    pub fn from_hashmap_with_hasher(map: HashMap<K, V>, bucket_count: usize, hash_builder: S) -> Self {
+        Builder::new()
+            .buckets(bucket_count)
+            .hasher(hash_builder)
+            .build_map_from(map)
+            .unwrap_or_else(|e| panic!("cannot create THashMap: {}", e))
+    }
+
+    /// Creates a map from pre-populated buckets. The caller is responsible for placing every key
+    /// in the bucket `bucket_index` assigns to it.
+    pub(crate) fn from_buckets(
+        buckets: Vec<MapBucket<K, V>>,
+        hash_builder: S,
+        policy: Option<ResizePolicy>,
+        counter_shards: Option<usize>,
+    ) -> Self {
+        THashMap {
+            buckets: Sharded::from_parts(buckets, hash_builder, policy, counter_shards),
+        }
+    }
+
+    /// Returns another handle to this map. Equivalent to `clone`.
+    pub fn handle(&self) -> Self
+    where
+        S: Clone,
+    {
+        self.clone()
+    }
+
+    /// Creates an independent map with the same contents, bucket count, hasher and resize
+    /// policy.
+    ///
+    /// Every bucket is part of the read set of the transaction.
+    pub fn deep_clone(&self, trans: &mut Transaction) -> StmResult<Self>
+    where
+        S: Clone,
+    {
+        let buckets = self.buckets.deep_clone(trans, |_, bucket| Ok(bucket))?;
+        Ok(THashMap { buckets })
+    }
+
+    /// Returns a reference to the map's `BuildHasher`.
+    pub fn hasher(&self) -> &S {
+        self.buckets.hasher()
+    }
+
+    /// Returns the current number of buckets.
+    pub fn bucket_count(&self, trans: &mut Transaction) -> StmResult<usize> {
+        self.buckets.bucket_count(trans)
+    }
+
+    /// Redistributes all entries over `bucket_count` buckets.
+    ///
+    /// This reads and replaces every bucket, so it conflicts with every concurrent transaction
+    /// on the map.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket_count` is zero.
+    pub fn resize(&self, trans: &mut Transaction, bucket_count: usize) -> StmResult<()> {
+        self.buckets.resize(trans, bucket_count)
+    }
+
+    /// Returns the bucket currently responsible for the given key.
+    ///
+    /// The bucket is only valid as long as the map is not resized, so it must not be used outside
+    /// of the transaction it was obtained in. Writing keys into a bucket they do not hash to
+    /// makes them unreachable.
+    #[cfg(feature = "raw-buckets")]
+    #[deprecated(note = "use `with_bucket`, which cannot break the bucket assignment")]
+    pub fn get_bucket(&self, trans: &mut Transaction, item: &K) -> StmResult<TVar<MapBucket<K, V>>> {
+        self.buckets.bucket_for(trans, item)
+    }
+
+    /// Runs `f` on the bucket responsible for the given key.
+    ///
+    /// The view passed to `f` only accepts insertions of keys that belong into this bucket, so
+    /// several related keys can be manipulated with a single bucket read and write. The bucket is
+    /// only written back if `f` modified it.
+    pub fn with_bucket<Q, F, R>(&self, trans: &mut Transaction, key: &Q, f: F) -> StmResult<R>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        F: FnOnce(&mut BucketView<K, V, S>) -> R,
+    {
+        let (buckets, index) = self.buckets.locate(trans, key)?;
+        let bucket = &buckets[index];
+        let map = bucket.read(trans)?;
+        let len_before = map.len();
+
+        let mut view = BucketView {
+            map,
+            hash_builder: self.buckets.hasher(),
+            index,
+            bucket_count: buckets.len(),
+            modified: false,
+        };
+        let result = f(&mut view);
+
+        if view.modified {
+            let len = view.map.len();
+            self.buckets.store(trans, bucket, len_before, view.map)?;
+            if len > len_before {
+                self.buckets.inserted(trans, len)?;
+            } else if len < len_before {
+                self.buckets.removed(trans, len)?;
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Inserts a key-value pair into the map.
+    ///
+    /// If the map did not have this key present, `None` is returned. Otherwise the value is
+    /// updated and the old value is returned.
+    ///
+    /// This function must be called inside a `atomically` block.
+    pub fn insert(&self, trans: &mut Transaction, key: K, value: V) -> StmResult<Option<V>> {
+        let bucket = self.buckets.bucket_for(trans, &key)?;
+        let mut map = bucket.read(trans)?;
+        let len_before = map.len();
+        let old = map.insert(key, value);
+        let len = map.len();
+        self.buckets.store(trans, &bucket, len_before, map)?;
+        if old.is_none() {
+            self.buckets.inserted(trans, len)?;
+        }
+
+        Ok(old)
+    }
+
+    /// Returns a copy of the value corresponding to the key.
+    pub fn get<Q>(&self, trans: &mut Transaction, key: &Q) -> StmResult<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let map = self.buckets.bucket_for(trans, key)?.read(trans)?;
+        Ok(map.get(key).cloned())
+    }
+
+    /// Returns a copy of the value corresponding to the key or `V::default()` if the key is not
+    /// present. The map itself is not modified.
+    pub fn get_or_default<Q>(&self, trans: &mut Transaction, key: &Q) -> StmResult<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        V: Default,
+    {
+        Ok(self.get(trans, key)?.unwrap_or_default())
+    }
+
+    /// Returns `true` if the map contains a value for the specified key.
+    pub fn contains_key<Q>(&self, trans: &mut Transaction, key: &Q) -> StmResult<bool>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let map = self.buckets.bucket_for(trans, key)?.read(trans)?;
+        Ok(map.contains_key(key))
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key was previously in
+    /// the map.
+    ///
+    /// The bucket is only written back if the key was present.
+    pub fn remove<Q>(&self, trans: &mut Transaction, key: &Q) -> StmResult<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        Ok(self.remove_entry(trans, key)?.map(|(_, value)| value))
+    }
+
+    /// Removes a key from the map, returning the stored key and value if the key was previously
+    /// in the map.
+    ///
+    /// The bucket is only written back if the key was present.
+    pub fn remove_entry<Q>(&self, trans: &mut Transaction, key: &Q) -> StmResult<Option<(K, V)>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let bucket = self.buckets.bucket_for(trans, key)?;
+        let mut map = bucket.read(trans)?;
+        let removed = map.remove_key_value(key);
+        if removed.is_some() {
+            let len = map.len();
+            self.buckets.store(trans, &bucket, len + 1, map)?;
+            self.buckets.removed(trans, len)?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Returns a copy of the value corresponding to the key, waiting for the key to appear.
+    ///
+    /// If the key is not present, the transaction is retried through `stm::retry`, which blocks
+    /// the calling thread until another transaction modifies the responsible bucket. `get` is the
+    /// non-blocking counterpart, `atomically_with_timeout` bounds the waiting time.
+    pub fn wait_for_key<Q>(&self, trans: &mut Transaction, key: &Q) -> StmResult<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        match self.get(trans, key)? {
+            Some(value) => Ok(value),
+            None => retry(),
+        }
+    }
+
+    /// Removes a key from the map and returns its value, waiting for the key to appear.
+    ///
+    /// Blocks like `wait_for_key` while the key is not present. `remove` is the non-blocking
+    /// counterpart.
+    pub fn remove_blocking<Q>(&self, trans: &mut Transaction, key: &Q) -> StmResult<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        match self.remove(trans, key)? {
+            Some(value) => Ok(value),
+            None => retry(),
+        }
+    }
+
+    /// Gets the given key's entry in the map for in-place manipulation.
+    ///
+    /// The responsible bucket is read once when the entry is created and written at most once by
+    /// the operation that completes the entry.
+    pub fn entry<'a>(&'a self, trans: &'a mut Transaction, key: K) -> StmResult<Entry<'a, K, V, S>> {
+        let bucket = self.buckets.bucket_for(trans, &key)?;
+        let map = bucket.read(trans)?;
+
+        Ok(Entry {
+            map: self,
+            trans,
+            bucket,
+            key,
+            state: EntryState::Pending(map),
+        })
+    }
+
+    /// Returns the number of entries in the map.
+    ///
+    /// Note that this reads every bucket, so the transaction conflicts with any concurrent
+    /// modification of the map. Maps built with `Builder::len_counter` read their element
+    /// counters instead.
+    pub fn len(&self, trans: &mut Transaction) -> StmResult<usize> {
+        self.buckets.len(trans)
+    }
+
+    /// Returns `true` if the map contains no entries.
+    pub fn is_empty(&self, trans: &mut Transaction) -> StmResult<bool> {
+        self.buckets.is_empty(trans)
+    }
+
+    /// Removes all entries, writing only buckets that are not empty yet.
+    pub fn clear(&self, trans: &mut Transaction) -> StmResult<()> {
+        let mut cleared = false;
+        for bucket in self.buckets.read(trans)?.iter() {
+            let len = bucket.read(trans)?.len();
+            if len > 0 {
+                self.buckets.store(trans, bucket, len, MapBucket::default())?;
+                cleared = true;
+            }
+        }
+
+        if cleared {
+            self.buckets.removed(trans, 0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Retains only the entries for which `f` returns `true`.
+    ///
+    /// Only buckets that actually lose entries are written back, so buckets without matching
+    /// entries only enter the read set.
+    pub fn retain<F>(&self, trans: &mut Transaction, mut f: F) -> StmResult<()>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.drain_filter(trans, |k, v| !f(k, v)).map(|_| ())
+    }
+
+    /// Removes all entries for which `f` returns `true` and returns them.
+    ///
+    /// Only buckets that actually lose entries are written back.
+    pub fn drain_filter<F>(&self, trans: &mut Transaction, mut f: F) -> StmResult<Vec<(K, V)>>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let mut result = Vec::new();
+        let mut min_len = None;
+
+        for bucket in self.buckets.read(trans)?.iter() {
+            let mut map = bucket.read(trans)?;
+            let matching: Vec<K> = map
+                .iter()
+                .filter(|(k, v)| f(k, v))
+                .map(|(k, _)| k.clone())
+                .collect();
+            if matching.is_empty() {
+                continue;
+            }
+
+            let len_before = map.len();
+            for key in &matching {
+                result.extend(map.remove_key_value(key));
+            }
+            min_len = Some(min_len.unwrap_or(usize::MAX).min(map.len()));
+            self.buckets.store(trans, bucket, len_before, map)?;
+        }
+
+        if let Some(len) = min_len {
+            self.buckets.removed(trans, len)?;
+        }
+
+        Ok(result)
+    }
+
+    /// Replaces values in place: every entry for which `f` returns `Some` gets the returned value.
+    ///
+    /// Only buckets in which at least one value was replaced are written back.
+    pub fn map_values_in_place<F>(&self, trans: &mut Transaction, mut f: F) -> StmResult<()>
+    where
+        F: FnMut(&K, &V) -> Option<V>,
+    {
+        for bucket in self.buckets.read(trans)?.iter() {
+            let mut map = bucket.read(trans)?;
+            let replacements: Vec<(K, V)> = map
+                .iter()
+                .filter_map(|(k, v)| f(k, v).map(|new| (k.clone(), new)))
+                .collect();
+            if replacements.is_empty() {
+                continue;
+            }
+
+            map.extend(replacements);
+            bucket.write(trans, map)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns an iterator over copies of all entries.
+    ///
+    /// Buckets are read lazily within the transaction as the iteration proceeds, so searches that
+    /// stop early (e.g. `find` or `any`) only add the buckets they visited to the read set. Every
+    /// item is a `StmResult`, as reading a bucket may fail; the iteration ends after an error.
+    pub fn iter<'a>(&'a self, trans: &'a mut Transaction) -> MapIter<'a, K, V> {
+        MapIter {
+            inner: self.buckets.iter(trans),
+        }
+    }
+
+    /// Returns an iterator over copies of all keys. Reads buckets lazily like `iter`.
+    pub fn keys<'a>(&'a self, trans: &'a mut Transaction) -> Keys<'a, K, V> {
+        Keys {
+            inner: self.iter(trans),
+        }
+    }
+
+    /// Returns an iterator over copies of all values. Reads buckets lazily like `iter`.
+    pub fn values<'a>(&'a self, trans: &'a mut Transaction) -> Values<'a, K, V> {
+        Values {
+            inner: self.iter(trans),
+        }
+    }
+
+    /// Returns a cursor that scans the map in chunks of `buckets_per_chunk` buckets, using one
+    /// transaction per chunk. A chunk size of zero is treated as one.
+    ///
+    /// See `MapCursor` for the consistency guarantees of such a scan.
+    pub fn cursor(&self, buckets_per_chunk: usize) -> MapCursor<'_, K, V, S> {
+        self.cursor_at(CursorPosition::default(), buckets_per_chunk)
+    }
+
+    /// Like `cursor`, but resumes a scan at a position returned by `MapCursor::position`.
+    pub fn cursor_at(&self, position: CursorPosition, buckets_per_chunk: usize) -> MapCursor<'_, K, V, S> {
+        MapCursor::new(&self.buckets, position, buckets_per_chunk)
+    }
+
+    /// Returns a copy of the whole map as read inside the given transaction.
+    ///
+    /// All buckets are part of the read set, so the result reflects a state of the map that
+    /// actually existed when the transaction commits.
+    pub fn snapshot(&self, trans: &mut Transaction) -> StmResult<HashMap<K, V>> {
+        let mut result = HashMap::new();
+
+        for bucket in self.buckets.read(trans)?.iter() {
+            result.extend(bucket.read(trans)?);
+        }
+
+        Ok(result)
+    }
+
+    /// Returns a consistent copy of the whole map.
+    ///
+    /// Runs `snapshot` in its own transaction, which is restarted until all buckets could be read
+    /// without interference from a concurrent commit. Must not be called inside a transaction.
+    pub fn get_contents(&self) -> HashMap<K,V> {
+        atomically(|trans| self.snapshot(trans))
+    }
+}
+
+
+/// Conditional updates, e.g. for optimistic protocols.
+///
+/// Every operation reads the responsible bucket once and only writes it back if the map changes.
+impl<K, V, S> THashMap<K, V, S>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+    S: BuildHasher,
+{
+    /// Replaces the value of `key` by `new` if it currently equals `expected`.
+    ///
+    /// Returns `Ok` with the previous value if it was replaced, and `Err` with a copy of the
+    /// current value otherwise, which is `None` if the key is not present.
+    pub fn compare_and_swap<Q>(
+        &self,
+        trans: &mut Transaction,
+        key: &Q,
+        expected: &V,
+        new: V,
+    ) -> StmResult<Result<V, Option<V>>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        V: PartialEq,
+    {
+        let bucket = self.buckets.bucket_for(trans, key)?;
+        let mut map = bucket.read(trans)?;
+
+        let old = match map.get_mut(key) {
+            Some(value) if value == expected => mem::replace(value, new),
+            current => return Ok(Err(current.cloned())),
+        };
+        bucket.write(trans, map)?;
+
+        Ok(Ok(old))
+    }
+
+    /// Applies `f` to the value of `key` if `pred` accepts the current value. The returned
+    /// `UpdateOutcome` tells whether the key was missing, the value rejected or updated.
+    pub fn update_if<Q, P, F>(
+        &self,
+        trans: &mut Transaction,
+        key: &Q,
+        pred: P,
+        f: F,
+    ) -> StmResult<UpdateOutcome<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        P: FnOnce(&V) -> bool,
+        F: FnOnce(&V) -> V,
+    {
+        let bucket = self.buckets.bucket_for(trans, key)?;
+        let mut map = bucket.read(trans)?;
+
+        let (old, new) = match map.get_mut(key) {
+            None => return Ok(UpdateOutcome::Missing),
+            Some(value) if !pred(value) => return Ok(UpdateOutcome::Rejected(value.clone())),
+            Some(value) => {
+                let new = f(value);
+                (mem::replace(value, new.clone()), new)
+            }
+        };
+        bucket.write(trans, map)?;
+
+        Ok(UpdateOutcome::Updated { old, new })
+    }
+
+    /// Replaces the value of `key` if the key is present. Returns the previous value, or `None`
+    /// if the key is not present, in which case nothing is inserted.
+    pub fn replace<Q>(&self, trans: &mut Transaction, key: &Q, value: V) -> StmResult<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let bucket = self.buckets.bucket_for(trans, key)?;
+        let mut map = bucket.read(trans)?;
+
+        let old = match map.get_mut(key) {
+            Some(slot) => mem::replace(slot, value),
+            None => return Ok(None),
+        };
+        bucket.write(trans, map)?;
+
+        Ok(Some(old))
+    }
+
+    /// Inserts the key-value pair if the key is not present. Returns `None` if the pair was
+    /// inserted, and a copy of the present value otherwise, in which case the map is unchanged.
+    pub fn insert_if_absent(&self, trans: &mut Transaction, key: K, value: V) -> StmResult<Option<V>> {
+        let bucket = self.buckets.bucket_for(trans, &key)?;
+        let mut map = bucket.read(trans)?;
+
+        if let Some(present) = map.get(&key) {
+            return Ok(Some(present.clone()));
+        }
+        map.insert(key, value);
+        let len = map.len();
+        self.buckets.store(trans, &bucket, len - 1, map)?;
+        self.buckets.inserted(trans, len)?;
+
+        Ok(None)
+    }
+}
+
+/// Operations on several keys at once.
+///
+/// Keys are grouped by bucket: every affected bucket is read once and, if it changed, written
+/// once at the end of the call, no matter how many of the keys it holds.
+impl<K, V, S> THashMap<K, V, S>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+    S: BuildHasher,
+{
+    /// Swaps the values of two keys. Returns `false` and leaves the map unchanged if either key
+    /// is not present.
+    pub fn swap<Q>(&self, trans: &mut Transaction, k1: &Q, k2: &Q) -> StmResult<bool>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let mut batch = Batch::new(self, trans)?;
+        let (v1, v2) = match (batch.get(trans, k1)?.cloned(), batch.get(trans, k2)?.cloned()) {
+            (Some(v1), Some(v2)) => (v1, v2),
+            _ => return Ok(false),
+        };
+
+        if k1 != k2 {
+            batch.insert_value(trans, k1, v2)?;
+            batch.insert_value(trans, k2, v1)?;
+        }
+        batch.commit(trans)?;
+
+        Ok(true)
+    }
+
+    /// Moves the value of `old` to the key `new`, replacing any value stored under `new`.
+    /// Returns `false` and leaves the map unchanged if `old` is not present.
+    pub fn rename<Q>(&self, trans: &mut Transaction, old: &Q, new: K) -> StmResult<bool>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if old == new.borrow() {
+            return self.contains_key(trans, old);
+        }
+
+        let mut batch = Batch::new(self, trans)?;
+        let value = match batch.remove(trans, old)? {
+            Some((_, value)) => value,
+            None => return Ok(false),
+        };
+        batch.insert(trans, new, value)?;
+        batch.commit(trans)?;
+
+        Ok(true)
+    }
+
+    /// Returns copies of the values of all given keys, in the order of `keys`.
+    pub fn get_many<Q>(&self, trans: &mut Transaction, keys: &[&Q]) -> StmResult<Vec<Option<V>>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let mut batch = Batch::new(self, trans)?;
+        keys.iter()
+            .map(|key| batch.get(trans, *key).map(Option::<&V>::cloned))
+            .collect()
+    }
+
+    /// Inserts all given entries. Returns the previous values of the keys, in the order of
+    /// `entries`; if a key occurs more than once, later occurrences see the earlier insertions.
+    pub fn insert_many<I>(&self, trans: &mut Transaction, entries: I) -> StmResult<Vec<Option<V>>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut batch = Batch::new(self, trans)?;
+        let old = entries
+            .into_iter()
+            .map(|(key, value)| batch.insert(trans, key, value))
+            .collect::<StmResult<_>>()?;
+        batch.commit(trans)?;
+
+        Ok(old)
+    }
+
+    /// Removes all given keys. Returns the removed values, in the order of `keys`.
+    pub fn remove_many<Q>(&self, trans: &mut Transaction, keys: &[&Q]) -> StmResult<Vec<Option<V>>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let mut batch = Batch::new(self, trans)?;
+        let removed = keys
+            .iter()
+            .map(|key| Ok(batch.remove(trans, *key)?.map(|(_, value)| value)))
+            .collect::<StmResult<_>>()?;
+        batch.commit(trans)?;
+
+        Ok(removed)
+    }
+}
+
+/// Moving entries between two maps.
+///
+/// Removal and insertion happen in the same transaction, so concurrent transactions observe every
+/// moved entry in exactly one of the maps.
+impl<K, V, S> THashMap<K, V, S>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+    S: BuildHasher,
+{
+    /// Moves the entry of `key` from `self` to `other`, replacing any value `other` holds for
+    /// that key. Returns `false` and changes neither map if `self` does not contain the key.
+    pub fn transfer_to<Q, S2>(
+        &self,
+        trans: &mut Transaction,
+        other: &THashMap<K, V, S2>,
+        key: &Q,
+    ) -> StmResult<bool>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        S2: BuildHasher,
+    {
+        match self.remove_entry(trans, key)? {
+            Some((key, value)) => {
+                other.insert(trans, key, value)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Moves all entries for which `f` returns `true` from `self` to `other`, replacing values
+    /// `other` holds for the same keys. Returns the number of moved entries.
+    ///
+    /// Every bucket of `self` is read, but only buckets that lose entries are written. The
+    /// receiving buckets of `other` are written once each.
+    pub fn transfer_where<F, S2>(
+        &self,
+        trans: &mut Transaction,
+        other: &THashMap<K, V, S2>,
+        f: F,
+    ) -> StmResult<usize>
+    where
+        F: FnMut(&K, &V) -> bool,
+        S2: BuildHasher,
+    {
+        let moved = self.drain_filter(trans, f)?;
+        let count = moved.len();
+        if count > 0 {
+            other.insert_many(trans, moved)?;
+        }
+
+        Ok(count)
+    }
+}
+
+impl<K, V, S> Default for THashMap<K, V, S> where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+    S: BuildHasher + Default,
+{
+    /// Creates an empty map with the default number of buckets.
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K, V, S> FromIterator<(K, V)> for THashMap<K, V, S>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+    S: BuildHasher + Default,
+{
+    /// Creates a map with the default number of buckets holding the given entries. If a key
+    /// occurs more than once, the last value wins.
+    ///
+    /// Entries are placed in their buckets directly, which is much cheaper than inserting them
+    /// one transaction at a time.
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Builder::new()
+            .hasher(S::default())
+            .build_map_from(iter)
+            .unwrap_or_else(|e| panic!("cannot create THashMap: {}", e))
+    }
+}
+
+impl<K, V, S, S2> From<HashMap<K, V, S2>> for THashMap<K, V, S>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+    S: BuildHasher + Default,
+{
+    fn from(map: HashMap<K, V, S2>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+impl<K, V, S> From<Vec<(K, V)>> for THashMap<K, V, S>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+    S: BuildHasher + Default,
+{
+    fn from(entries: Vec<(K, V)>) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+impl<K, V, S> Extend<(K, V)> for THashMap<K, V, S>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+    S: BuildHasher,
+{
+    /// Inserts all entries in a single transaction. Must not be called inside a transaction.
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let entries: Vec<(K, V)> = iter.into_iter().collect();
+        atomically(|trans| {
+            for (k, v) in entries.iter().cloned() {
+                self.insert(trans, k, v)?;
+            }
+            Ok(())
+        });
+    }
+}
+
+impl<K, V, S> IntoIterator for THashMap<K, V, S>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+    S: BuildHasher,
+{
+    type Item = (K, V);
+    type IntoIter = std::vec::IntoIter<(K, V)>;
+
+    /// Returns the entries of the map, read in a single transaction. Must not be called inside a
+    /// transaction.
+    fn into_iter(self) -> Self::IntoIter {
+        self.buckets.into_items().into_iter()
+    }
+}
+
+/// The result of `THashMap::update_if`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UpdateOutcome<V> {
+    /// The key is not present.
+    Missing,
+    /// The predicate rejected the current value, which is returned.
+    Rejected(V),
+    /// The value was updated.
+    Updated {
+        /// The value before the update.
+        old: V,
+        /// The value after the update.
+        new: V,
+    },
+}
+
+/// The buckets of a `THashMap` touched by a multi-key operation. Every bucket is read at most once
+/// and modified in place; `commit` writes back the modified ones.
+struct Batch<'a, K, V, S> {
+    map: &'a THashMap<K, V, S>,
+    array: Arc<Vec<TVar<MapBucket<K, V>>>>,
+    loaded: HashMap<usize, LoadedBucket<K, V>>,
+}
+
+struct LoadedBucket<K, V> {
+    content: MapBucket<K, V>,
+    len_before: usize,
+    modified: bool,
+}
+
+impl<'a, K, V, S> Batch<'a, K, V, S>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+    S: BuildHasher,
+{
+    fn new(map: &'a THashMap<K, V, S>, trans: &mut Transaction) -> StmResult<Self> {
+        Ok(Batch {
+            map,
+            array: map.buckets.read(trans)?,
+            loaded: HashMap::new(),
+        })
+    }
+
+    /// Returns the bucket responsible for `key`, reading it on first access.
+    fn bucket<Q>(&mut self, trans: &mut Transaction, key: &Q) -> StmResult<&mut LoadedBucket<K, V>>
+    where
+        Q: ?Sized + Hash,
+    {
+        let idx = bucket_index(self.map.hasher(), key, self.array.len());
+        if !self.loaded.contains_key(&idx) {
+            let content = self.array[idx].read(trans)?;
+            let len_before = content.len();
+            self.loaded.insert(
+                idx,
+                LoadedBucket {
+                    content,
+                    len_before,
+                    modified: false,
+                },
+            );
+        }
+
+        Ok(self.loaded.get_mut(&idx).unwrap())
+    }
+
+    fn get<Q>(&mut self, trans: &mut Transaction, key: &Q) -> StmResult<Option<&V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        Ok(self.bucket(trans, key)?.content.get(key))
+    }
+
+    fn insert(&mut self, trans: &mut Transaction, key: K, value: V) -> StmResult<Option<V>> {
+        let bucket = self.bucket(trans, &key)?;
+        bucket.modified = true;
+        Ok(bucket.content.insert(key, value))
+    }
+
+    /// Replaces the value of a key that is known to be present.
+    fn insert_value<Q>(&mut self, trans: &mut Transaction, key: &Q, value: V) -> StmResult<()>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let bucket = self.bucket(trans, key)?;
+        if let Some(slot) = bucket.content.get_mut(key) {
+            *slot = value;
+            bucket.modified = true;
+        }
+        Ok(())
+    }
+
+    fn remove<Q>(&mut self, trans: &mut Transaction, key: &Q) -> StmResult<Option<(K, V)>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let bucket = self.bucket(trans, key)?;
+        let removed = bucket.content.remove_key_value(key);
+        bucket.modified |= removed.is_some();
+        Ok(removed)
+    }
+
+    /// Writes back all modified buckets and lets the resize policy react to the changes.
+    fn commit(self, trans: &mut Transaction) -> StmResult<()> {
+        let mut max_grown = None;
+        let mut min_shrunk = None;
+
+        for (idx, bucket) in self.loaded {
+            if !bucket.modified {
+                continue;
+            }
+
+            let len = bucket.content.len();
+            if len > bucket.len_before {
+                max_grown = max_grown.max(Some(len));
+            } else if len < bucket.len_before {
+                min_shrunk = Some(min_shrunk.unwrap_or(len).min(len));
+            }
+            self.map.buckets.store(trans, &self.array[idx], bucket.len_before, bucket.content)?;
+        }
+
+        if let Some(len) = max_grown {
+            self.map.buckets.inserted(trans, len)?;
+        }
+        if let Some(len) = min_shrunk {
+            self.map.buckets.removed(trans, len)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Iterator over the entries of a `THashMap`, obtained through `THashMap::iter`.
+pub struct MapIter<'a, K, V>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+{
+    inner: BucketIter<'a, MapBucket<K, V>>,
+}
+
+impl<'a, K, V> Iterator for MapIter<'a, K, V>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+{
+    type Item = StmResult<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+/// Iterator over the keys of a `THashMap`, obtained through `THashMap::keys`.
+pub struct Keys<'a, K, V>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+{
+    inner: MapIter<'a, K, V>,
+}
+
+impl<'a, K, V> Iterator for Keys<'a, K, V>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+{
+    type Item = StmResult<K>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|entry| entry.map(|(k, _)| k))
+    }
+}
+
+/// Iterator over the values of a `THashMap`, obtained through `THashMap::values`.
+pub struct Values<'a, K, V>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+{
+    inner: MapIter<'a, K, V>,
+}
+
+impl<'a, K, V> Iterator for Values<'a, K, V>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+{
+    type Item = StmResult<V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|entry| entry.map(|(_, v)| v))
+    }
+}
+
+/// A view into a single entry of a `THashMap`, obtained through `THashMap::entry`.
+///
+/// Since the values live inside `TVar`s, the completing operations return copies of the value
+/// instead of references.
+pub struct Entry<'a, K, V, S = RandomState> {
+    map: &'a THashMap<K, V, S>,
+    trans: &'a mut Transaction,
+    bucket: TVar<MapBucket<K, V>>,
+    key: K,
+    state: EntryState<K, V>,
+}
+
+enum EntryState<K, V> {
+    /// The bucket has been read but not written yet.
+    Pending(MapBucket<K, V>),
+    /// The bucket has already been written by `and_modify`; holds a copy of the new value.
+    Modified(V),
+}
+
+impl<'a, K, V, S> Entry<'a, K, V, S>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+    S: BuildHasher,
+{
+    /// Returns a reference to this entry's key.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Returns a reference to the current value of this entry, if any.
+    pub fn get(&self) -> Option<&V> {
+        match &self.state {
+            EntryState::Pending(map) => map.get(&self.key),
+            EntryState::Modified(value) => Some(value),
+        }
+    }
+
+    /// Provides in-place mutable access to an occupied entry. Vacant entries are left untouched.
+    ///
+    /// The modification is written back immediately, so it takes effect even if the entry is not
+    /// completed afterwards.
+    pub fn and_modify<F>(self, f: F) -> StmResult<Self>
+    where
+        F: FnOnce(&mut V),
+    {
+        let Entry {
+            map: owner,
+            trans,
+            bucket,
+            key,
+            state,
+        } = self;
+
+        let state = match state {
+            EntryState::Pending(mut map) => match map.get_mut(&key) {
+                Some(value) => {
+                    f(value);
+                    let value = value.clone();
+                    bucket.write(trans, map)?;
+                    EntryState::Modified(value)
+                }
+                None => EntryState::Pending(map),
+            },
+            EntryState::Modified(mut value) => {
+                // already written once, so the bucket has to be updated from the transaction log
+                f(&mut value);
+                let mut map = bucket.read(trans)?;
+                map.insert(key.clone(), value.clone());
+                bucket.write(trans, map)?;
+                EntryState::Modified(value)
+            }
+        };
+
+        Ok(Entry {
+            map: owner,
+            trans,
+            bucket,
+            key,
+            state,
+        })
+    }
+
+    /// Ensures a value is in the entry by inserting the default if empty. Returns a copy of the
+    /// value in the entry.
+    pub fn or_insert(self, default: V) -> StmResult<V> {
+        self.or_insert_with(|| default)
+    }
+
+    /// Ensures a value is in the entry by inserting the result of the default function if empty.
+    /// Returns a copy of the value in the entry.
+    pub fn or_insert_with<F>(self, default: F) -> StmResult<V>
+    where
+        F: FnOnce() -> V,
+    {
+        match self.state {
+            EntryState::Pending(mut map) => {
+                if let Some(value) = map.get(&self.key) {
+                    return Ok(value.clone());
+                }
+
+                let value = default();
+                map.insert(self.key, value.clone());
+                let len = map.len();
+                self.map.buckets.store(self.trans, &self.bucket, len - 1, map)?;
+                self.map.buckets.inserted(self.trans, len)?;
+                Ok(value)
+            }
+            EntryState::Modified(value) => Ok(value),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting `V::default()` if empty. Returns a copy of the
+    /// value in the entry.
+    pub fn or_default(self) -> StmResult<V>
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Removes the entry from the map, returning the stored key and value if it was occupied.
+    pub fn remove_entry(self) -> StmResult<Option<(K, V)>> {
+        let mut map = match self.state {
+            EntryState::Pending(map) => map,
+            EntryState::Modified(_) => self.bucket.read(self.trans)?,
+        };
+
+        let removed = map.remove_key_value(&self.key);
+        if removed.is_some() {
+            let len = map.len();
+            self.map.buckets.store(self.trans, &self.bucket, len + 1, map)?;
+            self.map.buckets.removed(self.trans, len)?;
+        }
+
+        Ok(removed)
+    }
+}
+
+/// Access to a single bucket of a `THashMap`, obtained through `THashMap::with_bucket`.
+///
+/// Lookups and removals behave like on a `HashMap` that only holds the entries of this bucket.
+/// Insertions are rejected for keys that hash into a different bucket.
+pub struct BucketView<'a, K, V, S = RandomState> {
+    map: MapBucket<K, V>,
+    hash_builder: &'a S,
+    index: usize,
+    bucket_count: usize,
+    modified: bool,
+}
+
+impl<'a, K, V, S> BucketView<'a, K, V, S>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+    S: BuildHasher,
+{
+    /// Returns `true` if the given key belongs into this bucket.
+    pub fn covers<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Hash,
+    {
+        bucket_index(self.hash_builder, key, self.bucket_count) == self.index
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.get(key)
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let value = self.map.get_mut(key);
+        self.modified |= value.is_some();
+        value
+    }
+
+    /// Returns `true` if the bucket contains a value for the specified key.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Inserts a key-value pair into the bucket and returns the previous value of the key.
+    ///
+    /// Fails and hands back key and value if the key belongs into a different bucket.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, ForeignKeyError<K, V>> {
+        if !self.covers(&key) {
+            return Err(ForeignKeyError { key, value });
+        }
+
+        self.modified = true;
+        Ok(self.map.insert(key, value))
+    }
+
+    /// Removes a key from the bucket, returning its value if it was present.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let removed = self.map.remove(key);
+        self.modified |= removed.is_some();
+        removed
+    }
+
+    /// Returns the number of entries in this bucket.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if this bucket holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Iterates over the entries of this bucket.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.map.iter()
+    }
+}
@@ -0,0 +1,771 @@
+use std::any::Any;
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::collections::HashSet;
+use std::hash::{BuildHasher, Hash};
+use std::iter::FromIterator;
+use std::thread;
+use stm::{atomically, retry, StmResult, Transaction};
+
+use crate::cursor::{CursorPosition, SetCursor};
+use crate::sharded::{BucketIter, Sharded};
+use crate::storage::{SetBucket, SetStorage};
+use crate::{bucket_index, default_bucket_count, Builder, ConfigError, ResizePolicy};
+
+/// A transaction-ready hash set with a configurable number of buckets.
+///
+/// Values are distributed over the buckets using the `BuildHasher` `S`, which defaults to the
+/// randomly seeded `RandomState` of the standard library. The bucket count can be changed with
+/// `resize` or automatically by configuring a `ResizePolicy` through the `Builder`.
+///
+/// Cloning a `THashSet` is cheap and returns a handle to the same set: the buckets live in
+/// `TVar`s, which are shared between clones, so a change made through one handle is visible
+/// through all others. `handle` does the same but makes the intent explicit, while `deep_clone`
+/// creates an independent copy.
+#[derive(Clone)]
+pub struct THashSet<T, S = RandomState> {
+    buckets: Sharded<SetBucket<T>, S>,
+}
+
+impl<T> THashSet<T, RandomState>
+where
+    T: Any + Clone + Eq + Hash + Send + Sync,
+{
+    /// Creates a new transaction-ready HashSet with the given number of buckets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket_count` is zero. See `try_new` for a fallible version.
+    pub fn new(bucket_count: usize) -> Self {
+        Self::with_buckets_and_hasher(bucket_count, RandomState::new())
+    }
+
+    /// Creates a new transaction-ready HashSet with the given number of buckets.
+    ///
+    /// Returns an error if `bucket_count` is zero.
+    pub fn try_new(bucket_count: usize) -> Result<Self, ConfigError> {
+        Self::try_with_buckets_and_hasher(bucket_count, RandomState::new())
+    }
+}
+
+impl<T, S> THashSet<T, S>
+where
+    T: Any + Clone + Eq + Hash + Send + Sync,
+    S: BuildHasher,
+{
+    /// Creates a new transaction-ready HashSet with the default number of buckets which uses the
+    /// given hash builder to distribute values over the buckets.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self::with_buckets_and_hasher(default_bucket_count(), hash_builder)
+    }
+
+    /// Creates a new transaction-ready HashSet with the given number of buckets which uses the
+    /// given hash builder to distribute values over the buckets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket_count` is zero. See `try_with_buckets_and_hasher` for a fallible version.
+    pub fn with_buckets_and_hasher(bucket_count: usize, hash_builder: S) -> Self {
+        Self::try_with_buckets_and_hasher(bucket_count, hash_builder)
+            .unwrap_or_else(|e| panic!("cannot create THashSet: {}", e))
+    }
+
+    /// Creates a new transaction-ready HashSet with the given number of buckets which uses the
+    /// given hash builder to distribute values over the buckets.
+    ///
+    /// Returns an error if `bucket_count` is zero.
+    pub fn try_with_buckets_and_hasher(bucket_count: usize, hash_builder: S) -> Result<Self, ConfigError> {
+        Builder::new()
+            .buckets(bucket_count)
+            .hasher(hash_builder)
+            .build_set()
+    }
+
+    /// Creates a set from pre-populated buckets. The caller is responsible for placing every
+    /// value in the bucket `bucket_index` assigns to it.
+    pub(crate) fn from_buckets(
+        buckets: Vec<SetBucket<T>>,
+        hash_builder: S,
+        policy: Option<ResizePolicy>,
+        counter_shards: Option<usize>,
+    ) -> Self {
+        THashSet {
+            buckets: Sharded::from_parts(buckets, hash_builder, policy, counter_shards),
+        }
+    }
+
+    /// Returns another handle to this set. Equivalent to `clone`.
+    pub fn handle(&self) -> Self
+    where
+        S: Clone,
+    {
+        self.clone()
+    }
+
+    /// Creates an independent set with the same contents, bucket count, hasher and resize
+    /// policy.
+    ///
+    /// Every bucket is part of the read set of the transaction.
+    pub fn deep_clone(&self, trans: &mut Transaction) -> StmResult<Self>
+    where
+        S: Clone,
+    {
+        let buckets = self.buckets.deep_clone(trans, |_, bucket| Ok(bucket))?;
+        Ok(THashSet { buckets })
+    }
+
+    /// Returns a reference to the set's `BuildHasher`.
+    pub fn hasher(&self) -> &S {
+        self.buckets.hasher()
+    }
+
+    /// Returns the current number of buckets.
+    pub fn bucket_count(&self, trans: &mut Transaction) -> StmResult<usize> {
+        self.buckets.bucket_count(trans)
+    }
+
+    /// Redistributes all values over `bucket_count` buckets.
+    ///
+    /// This reads and replaces every bucket, so it conflicts with every concurrent transaction
+    /// on the set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket_count` is zero.
+    pub fn resize(&self, trans: &mut Transaction, bucket_count: usize) -> StmResult<()> {
+        self.buckets.resize(trans, bucket_count)
+    }
+
+    /// Adds a value to the set.
+    ///
+    /// If the set did not have this value present, `true` is returned. If the value has been
+    /// present before, `false` is returned.
+    ///
+    /// This function must be called inside a `atomically` block.
+    pub fn insert(&self, trans: &mut Transaction, value: T) -> StmResult<bool> {
+        let bucket = self.buckets.bucket_for(trans, &value)?;
+        let mut set = bucket.read(trans)?;
+
+        if set.insert_value(value) {
+            // the element is indeed new -- write back, so the check above and the insertion
+            // become part of the same transaction
+            let len = set.len();
+            self.buckets.store(trans, &bucket, len - 1, set)?;
+            self.buckets.inserted(trans, len)?;
+            Ok(true)
+        } else {
+            // nothing to be inserted, no change to hashset made
+            Ok(false)
+        }
+    }
+
+    /// Returns `true` if the set contains a value.
+    pub fn contains<Q>(&self, trans: &mut Transaction, value: &Q) -> StmResult<bool>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let set = self.buckets.bucket_for(trans, value)?.read(trans)?;
+        Ok(set.contains(value))
+    }
+
+    /// Removes a value from the set. Returns whether the value was present in the set.
+    ///
+    /// The bucket is only written back if the value was present.
+    pub fn remove<Q>(&self, trans: &mut Transaction, value: &Q) -> StmResult<bool>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        Ok(self.take(trans, value)?.is_some())
+    }
+
+    /// Removes and returns the value in the set, if any, that is equal to the given one.
+    pub fn take<Q>(&self, trans: &mut Transaction, value: &Q) -> StmResult<Option<T>>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let bucket = self.buckets.bucket_for(trans, value)?;
+        let mut set = bucket.read(trans)?;
+        let taken = set.take_value(value);
+        if taken.is_some() {
+            let len = set.len();
+            self.buckets.store(trans, &bucket, len + 1, set)?;
+            self.buckets.removed(trans, len)?;
+        }
+
+        Ok(taken)
+    }
+
+    /// Returns the number of elements in the set.
+    ///
+    /// Note that this reads every bucket, so the transaction conflicts with any concurrent
+    /// modification of the set. Sets built with `Builder::len_counter` read their element
+    /// counters instead.
+    pub fn len(&self, trans: &mut Transaction) -> StmResult<usize> {
+        self.buckets.len(trans)
+    }
+
+    /// Returns `true` if the set contains no elements.
+    pub fn is_empty(&self, trans: &mut Transaction) -> StmResult<bool> {
+        self.buckets.is_empty(trans)
+    }
+
+    /// Clears the set, removing all values.
+    ///
+    /// Buckets that are already empty are not written.
+    pub fn clear(&self, trans: &mut Transaction) -> StmResult<()> {
+        let mut cleared = false;
+        for bucket in self.buckets.read(trans)?.iter() {
+            let len = bucket.read(trans)?.len();
+            if len > 0 {
+                self.buckets.store(trans, bucket, len, SetBucket::default())?;
+                cleared = true;
+            }
+        }
+
+        if cleared {
+            self.buckets.removed(trans, 0)?;
+        }
+
+        Ok(())
+    }
+
+    /// Empties the HashSet and returns all elements as `Vec`.
+    ///
+    /// Must be executed as part of a transaction. The emptied buckets are written back in the same
+    /// transaction, so every element is handed out exactly once even with concurrent consumers.
+    /// Buckets that are already empty are not written.
+    pub fn drain(&self, trans: &mut Transaction) -> StmResult<Vec<T>> {
+        let mut result = Vec::new();
+
+        for bucket in self.buckets.read(trans)?.iter() {
+            let set = bucket.read(trans)?;
+            let len = set.len();
+            if len > 0 {
+                result.extend(set);
+                self.buckets.store(trans, bucket, len, SetBucket::default())?;
+            }
+        }
+
+        if !result.is_empty() {
+            self.buckets.removed(trans, 0)?;
+        }
+
+        Ok(result)
+    }
+
+    /// Removes and returns an arbitrary value from the set.
+    ///
+    /// If the set is empty, the transaction is retried through `stm::retry`, which blocks the
+    /// calling thread until another transaction modifies the set. This makes the set usable as a
+    /// work bag for a pool of workers. See `try_pop_any` for a non-blocking version and
+    /// `atomically_with_timeout` to bound the waiting time.
+    pub fn pop_any(&self, trans: &mut Transaction) -> StmResult<T> {
+        match self.try_pop_any(trans)? {
+            Some(value) => Ok(value),
+            None => retry(),
+        }
+    }
+
+    /// Removes and returns an arbitrary value from the set, or returns `None` immediately if the
+    /// set is empty.
+    pub fn try_pop_any(&self, trans: &mut Transaction) -> StmResult<Option<T>> {
+        let buckets = self.buckets.read(trans)?;
+
+        // start at a different bucket in every thread, so that concurrent workers do not all
+        // compete for the first non-empty bucket
+        let start = bucket_index(self.hasher(), &thread::current().id(), buckets.len());
+
+        for bucket in buckets[start..].iter().chain(buckets[..start].iter()) {
+            let mut set = bucket.read(trans)?;
+            let value = match set.iter().next() {
+                Some(value) => value.clone(),
+                None => continue,
+            };
+
+            set.take_value(&value);
+            let len = set.len();
+            self.buckets.store(trans, bucket, len + 1, set)?;
+            self.buckets.removed(trans, len)?;
+            return Ok(Some(value));
+        }
+
+        Ok(None)
+    }
+
+    /// Returns an iterator over copies of all values.
+    ///
+    /// Buckets are read lazily within the transaction as the iteration proceeds, so searches that
+    /// stop early (e.g. `find` or `any`) only add the buckets they visited to the read set. Every
+    /// item is a `StmResult`, as reading a bucket may fail; the iteration ends after an error.
+    pub fn iter<'a>(&'a self, trans: &'a mut Transaction) -> SetIter<'a, T> {
+        SetIter {
+            inner: self.buckets.iter(trans),
+        }
+    }
+
+    /// Retains only the values for which `f` returns `true`.
+    ///
+    /// Only buckets that actually lose values are written back, so buckets without matching
+    /// values only enter the read set.
+    pub fn retain<F>(&self, trans: &mut Transaction, mut f: F) -> StmResult<()>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.drain_filter(trans, |v| !f(v)).map(|_| ())
+    }
+
+    /// Removes all values for which `f` returns `true` and returns them.
+    ///
+    /// Only buckets that actually lose values are written back.
+    pub fn drain_filter<F>(&self, trans: &mut Transaction, mut f: F) -> StmResult<Vec<T>>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let mut result = Vec::new();
+        let mut min_len = None;
+
+        for bucket in self.buckets.read(trans)?.iter() {
+            let mut set = bucket.read(trans)?;
+            let matching: Vec<T> = set.iter().filter(|v| f(v)).cloned().collect();
+            if matching.is_empty() {
+                continue;
+            }
+
+            let len_before = set.len();
+            for value in &matching {
+                set.take_value(value);
+            }
+            min_len = Some(min_len.unwrap_or(usize::MAX).min(set.len()));
+            self.buckets.store(trans, bucket, len_before, set)?;
+            result.extend(matching);
+        }
+
+        if let Some(len) = min_len {
+            self.buckets.removed(trans, len)?;
+        }
+
+        Ok(result)
+    }
+
+    /// Replaces values in place: every value for which `f` returns `Some` is replaced by the
+    /// returned value. `f` only sees the values the set held before the call. Replacements that are
+    /// equal to another value of the set are merged with it.
+    ///
+    /// Changed values are moved to the buckets they hash to, and only buckets that actually gain
+    /// or lose values are written back.
+    pub fn map_values_in_place<F>(&self, trans: &mut Transaction, mut f: F) -> StmResult<()>
+    where
+        F: FnMut(&T) -> Option<T>,
+    {
+        let array = self.buckets.read(trans)?;
+        let mut contents = Vec::with_capacity(array.len());
+        let mut lens_before = Vec::with_capacity(array.len());
+        let mut replacements = Vec::new();
+        let mut modified = vec![false; array.len()];
+
+        for (idx, bucket) in array.iter().enumerate() {
+            let mut set = bucket.read(trans)?;
+            lens_before.push(set.len());
+            let changes: Vec<(T, T)> = set
+                .iter()
+                .filter_map(|v| f(v).filter(|new| new != v).map(|new| (v.clone(), new)))
+                .collect();
+
+            for (old, new) in changes {
+                set.take_value(&old);
+                replacements.push(new);
+                modified[idx] = true;
+            }
+            contents.push(set);
+        }
+
+        for value in replacements {
+            let idx = bucket_index(self.hasher(), &value, array.len());
+            modified[idx] |= contents[idx].insert_value(value);
+        }
+
+        let mut max_len = None;
+        let mut min_len = None;
+        let changes = array.iter().zip(contents).zip(modified).zip(lens_before);
+        for (((var, bucket), modified), len_before) in changes {
+            if !modified {
+                continue;
+            }
+
+            let len = bucket.len();
+            if len > len_before {
+                max_len = Some(max_len.unwrap_or(0).max(len));
+            } else if len < len_before {
+                min_len = Some(min_len.unwrap_or(len).min(len));
+            }
+            self.buckets.store(trans, var, len_before, bucket)?;
+        }
+
+        if let Some(len) = max_len {
+            self.buckets.inserted(trans, len)?;
+        }
+        match min_len {
+            Some(len) => self.buckets.removed(trans, len),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns a cursor that scans the set in chunks of `buckets_per_chunk` buckets, using one
+    /// transaction per chunk. A chunk size of zero is treated as one.
+    ///
+    /// See `SetCursor` for the consistency guarantees of such a scan.
+    pub fn cursor(&self, buckets_per_chunk: usize) -> SetCursor<'_, T, S> {
+        self.cursor_at(CursorPosition::default(), buckets_per_chunk)
+    }
+
+    /// Like `cursor`, but resumes a scan at a position returned by `SetCursor::position`.
+    pub fn cursor_at(&self, position: CursorPosition, buckets_per_chunk: usize) -> SetCursor<'_, T, S> {
+        SetCursor::new(&self.buckets, position, buckets_per_chunk)
+    }
+
+    /// Returns a copy of all elements as `Vec` without modifying the set.
+    pub fn to_vec(&self, trans: &mut Transaction) -> StmResult<Vec<T>> {
+        let mut result = Vec::new();
+
+        for bucket in self.buckets.read(trans)?.iter() {
+            result.extend(bucket.read(trans)?);
+        }
+
+        Ok(result)
+    }
+
+    /// Returns a copy of all elements as `Vec`.
+    ///
+    /// Despite what earlier versions of this documentation claimed, the set is *not* emptied.
+    #[deprecated(note = "use `to_vec` for a snapshot or `drain` to empty the set")]
+    pub fn as_vec(&self, trans: &mut Transaction) -> StmResult<Vec<T>> {
+        self.to_vec(trans)
+    }
+}
+
+/// Iterator over the values of a `THashSet`, obtained through `THashSet::iter`.
+pub struct SetIter<'a, T>
+where
+    T: Any + Clone + Eq + Hash + Send + Sync,
+{
+    inner: BucketIter<'a, SetBucket<T>>,
+}
+
+impl<'a, T> Iterator for SetIter<'a, T>
+where
+    T: Any + Clone + Eq + Hash + Send + Sync,
+{
+    type Item = StmResult<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+/// Set algebra between two sets.
+///
+/// The other set may use a different hasher and bucket count. Values of one set are looked up in
+/// the other by hashing them with the other set's hasher, unless both sets share their hasher
+/// (one is a handle or a deep clone of the other) and bucket count. Then every value is only
+/// compared with the bucket at the same index.
+impl<T, S> THashSet<T, S>
+where
+    T: Any + Clone + Eq + Hash + Send + Sync,
+    S: BuildHasher,
+{
+    /// Returns all values that are in `self`, in `other`, or in both.
+    pub fn union<S2>(&self, trans: &mut Transaction, other: &THashSet<T, S2>) -> StmResult<HashSet<T>>
+    where
+        S2: BuildHasher,
+    {
+        let mut result: HashSet<T> = self.to_vec(trans)?.into_iter().collect();
+        result.extend(other.to_vec(trans)?);
+
+        Ok(result)
+    }
+
+    /// Returns all values that are both in `self` and in `other`.
+    pub fn intersection<S2>(&self, trans: &mut Transaction, other: &THashSet<T, S2>) -> StmResult<HashSet<T>>
+    where
+        S2: BuildHasher,
+    {
+        let (own, others) = self.read_pair(trans, other)?;
+        let mut result = HashSet::new();
+
+        for (idx, bucket) in own.buckets.iter().enumerate() {
+            result.extend(bucket.iter().filter(|v| others.contains(idx, v)).cloned());
+        }
+
+        Ok(result)
+    }
+
+    /// Returns all values that are in `self` but not in `other`.
+    pub fn difference<S2>(&self, trans: &mut Transaction, other: &THashSet<T, S2>) -> StmResult<HashSet<T>>
+    where
+        S2: BuildHasher,
+    {
+        let (own, others) = self.read_pair(trans, other)?;
+        let mut result = HashSet::new();
+
+        for (idx, bucket) in own.buckets.iter().enumerate() {
+            result.extend(bucket.iter().filter(|v| !others.contains(idx, v)).cloned());
+        }
+
+        Ok(result)
+    }
+
+    /// Returns all values that are in exactly one of `self` and `other`.
+    pub fn symmetric_difference<S2>(
+        &self,
+        trans: &mut Transaction,
+        other: &THashSet<T, S2>,
+    ) -> StmResult<HashSet<T>>
+    where
+        S2: BuildHasher,
+    {
+        let (own, others) = self.read_pair(trans, other)?;
+        let mut result = HashSet::new();
+
+        for (idx, bucket) in own.buckets.iter().enumerate() {
+            result.extend(bucket.iter().filter(|v| !others.contains(idx, v)).cloned());
+        }
+        for (idx, bucket) in others.buckets.iter().enumerate() {
+            result.extend(bucket.iter().filter(|v| !own.contains(idx, v)).cloned());
+        }
+
+        Ok(result)
+    }
+
+    /// Returns `true` if every value of `self` is also in `other`.
+    pub fn is_subset<S2>(&self, trans: &mut Transaction, other: &THashSet<T, S2>) -> StmResult<bool>
+    where
+        S2: BuildHasher,
+    {
+        let (own, others) = self.read_pair(trans, other)?;
+
+        let mut buckets = own.buckets.iter().enumerate();
+        Ok(buckets.all(|(idx, bucket)| bucket.iter().all(|v| others.contains(idx, v))))
+    }
+
+    /// Returns `true` if every value of `other` is also in `self`.
+    pub fn is_superset<S2>(&self, trans: &mut Transaction, other: &THashSet<T, S2>) -> StmResult<bool>
+    where
+        S2: BuildHasher,
+    {
+        other.is_subset(trans, self)
+    }
+
+    /// Returns `true` if `self` and `other` have no values in common.
+    pub fn is_disjoint<S2>(&self, trans: &mut Transaction, other: &THashSet<T, S2>) -> StmResult<bool>
+    where
+        S2: BuildHasher,
+    {
+        let (own, others) = self.read_pair(trans, other)?;
+
+        let mut buckets = own.buckets.iter().enumerate();
+        Ok(buckets.all(|(idx, bucket)| bucket.iter().all(|v| !others.contains(idx, v))))
+    }
+
+    /// Adds all values of `other` to `self`.
+    ///
+    /// Only the buckets of `self` that actually receive new values are written.
+    pub fn extend_from<S2>(&self, trans: &mut Transaction, other: &THashSet<T, S2>) -> StmResult<()>
+    where
+        S2: BuildHasher,
+    {
+        let array = self.buckets.read(trans)?;
+        let (own, others) = self.read_pair(trans, other)?;
+        let aligned = own.aligned;
+        let mut own = own.buckets;
+        let lens_before: Vec<usize> = own.iter().map(|bucket| bucket.len()).collect();
+        let mut modified = vec![false; own.len()];
+
+        for (other_idx, bucket) in others.buckets.into_iter().enumerate() {
+            for value in bucket {
+                let idx = if aligned {
+                    other_idx
+                } else {
+                    bucket_index(self.hasher(), &value, own.len())
+                };
+                modified[idx] |= own[idx].insert_value(value);
+            }
+        }
+
+        let mut max_len = 0;
+        let changes = array.iter().zip(own).zip(modified).zip(lens_before);
+        for (((var, bucket), modified), len_before) in changes {
+            if modified {
+                max_len = max_len.max(bucket.len());
+                self.buckets.store(trans, var, len_before, bucket)?;
+            }
+        }
+
+        self.buckets.inserted(trans, max_len)
+    }
+
+    /// Removes all values from `self` that are not in `other`.
+    ///
+    /// Only the buckets of `self` that actually lose values are written.
+    pub fn retain_in<S2>(&self, trans: &mut Transaction, other: &THashSet<T, S2>) -> StmResult<()>
+    where
+        S2: BuildHasher,
+    {
+        let array = self.buckets.read(trans)?;
+        let (own, others) = self.read_pair(trans, other)?;
+        let mut min_len = None;
+
+        for (idx, (var, mut bucket)) in array.iter().zip(own.buckets).enumerate() {
+            let len = bucket.len();
+            bucket.retain(|v| others.contains(idx, v));
+            if bucket.len() < len {
+                min_len = Some(min_len.unwrap_or(len).min(bucket.len()));
+                self.buckets.store(trans, var, len, bucket)?;
+            }
+        }
+
+        match min_len {
+            Some(len) => self.buckets.removed(trans, len),
+            None => Ok(()),
+        }
+    }
+
+    /// Reads all buckets of `self` and `other` and checks whether their layouts are aligned.
+    fn read_pair<'a, S2>(
+        &'a self,
+        trans: &mut Transaction,
+        other: &'a THashSet<T, S2>,
+    ) -> StmResult<(ReadBuckets<'a, T, S>, ReadBuckets<'a, T, S2>)>
+    where
+        S2: BuildHasher,
+    {
+        let mut own = ReadBuckets::read(trans, &self.buckets)?;
+        let mut others = ReadBuckets::read(trans, &other.buckets)?;
+
+        let aligned =
+            self.buckets.shares_hasher(&other.buckets) && own.buckets.len() == others.buckets.len();
+        own.aligned = aligned;
+        others.aligned = aligned;
+
+        Ok((own, others))
+    }
+}
+
+/// The contents of all buckets of a set, read within a transaction.
+struct ReadBuckets<'a, T, S> {
+    buckets: Vec<SetBucket<T>>,
+    hash_builder: &'a S,
+    /// Whether values of the partner set are stored at the same bucket index as in this one.
+    aligned: bool,
+}
+
+impl<'a, T, S> ReadBuckets<'a, T, S>
+where
+    T: Any + Clone + Eq + Hash + Send + Sync,
+    S: BuildHasher,
+{
+    fn read(trans: &mut Transaction, buckets: &'a Sharded<SetBucket<T>, S>) -> StmResult<Self> {
+        let mut contents = Vec::new();
+        for bucket in buckets.read(trans)?.iter() {
+            contents.push(bucket.read(trans)?);
+        }
+
+        Ok(ReadBuckets {
+            buckets: contents,
+            hash_builder: buckets.hasher(),
+            aligned: false,
+        })
+    }
+
+    /// Checks whether `value`, taken from bucket `partner_idx` of the partner set, is contained.
+    fn contains(&self, partner_idx: usize, value: &T) -> bool {
+        let idx = if self.aligned {
+            partner_idx
+        } else {
+            bucket_index(self.hash_builder, value, self.buckets.len())
+        };
+        self.buckets[idx].contains(value)
+    }
+}
+
+impl<T, S> Default for THashSet<T, S>
+where
+    T: Any + Clone + Eq + Hash + Send + Sync,
+    S: BuildHasher + Default,
+{
+    /// Creates an empty set with the default number of buckets.
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<T, S> FromIterator<T> for THashSet<T, S>
+where
+    T: Any + Clone + Eq + Hash + Send + Sync,
+    S: BuildHasher + Default,
+{
+    /// Creates a set with the default number of buckets holding the given values.
+    ///
+    /// Values are placed in their buckets directly, which is much cheaper than inserting them one
+    /// transaction at a time.
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Builder::new()
+            .hasher(S::default())
+            .build_set_from(iter)
+            .unwrap_or_else(|e| panic!("cannot create THashSet: {}", e))
+    }
+}
+
+impl<T, S, S2> From<HashSet<T, S2>> for THashSet<T, S>
+where
+    T: Any + Clone + Eq + Hash + Send + Sync,
+    S: BuildHasher + Default,
+{
+    fn from(set: HashSet<T, S2>) -> Self {
+        set.into_iter().collect()
+    }
+}
+
+impl<T, S> From<Vec<T>> for THashSet<T, S>
+where
+    T: Any + Clone + Eq + Hash + Send + Sync,
+    S: BuildHasher + Default,
+{
+    fn from(values: Vec<T>) -> Self {
+        values.into_iter().collect()
+    }
+}
+
+impl<T, S> Extend<T> for THashSet<T, S>
+where
+    T: Any + Clone + Eq + Hash + Send + Sync,
+    S: BuildHasher,
+{
+    /// Inserts all values in a single transaction. Must not be called inside a transaction.
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let values: Vec<T> = iter.into_iter().collect();
+        atomically(|trans| {
+            for value in values.iter().cloned() {
+                self.insert(trans, value)?;
+            }
+            Ok(())
+        });
+    }
+}
+
+impl<T, S> IntoIterator for THashSet<T, S>
+where
+    T: Any + Clone + Eq + Hash + Send + Sync,
+    S: BuildHasher,
+{
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    /// Returns the values of the set, read in a single transaction. Must not be called inside a
+    /// transaction.
+    fn into_iter(self) -> Self::IntoIter {
+        self.buckets.into_items().into_iter()
+    }
+}
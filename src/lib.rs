@@ -1,128 +1,49 @@
 //! This library provides a small set of data types for use with the
 //! [stm](https://crates.io/crates/stm) crate.
 
-use std::any::Any;
-use std::collections::hash_map::DefaultHasher;
-use std::collections::{HashMap, HashSet};
-use std::hash::{Hash, Hasher};
-use stm::{StmResult, TVar, Transaction};
-
-/// A transaction-ready hash set with a configurable but fixed number of buckets.
-#[derive(Clone)]
-pub struct THashSet<T> {
-    contents: Vec<TVar<HashSet<T>>>,
+mod builder;
+mod cursor;
+mod error;
+mod finemap;
+mod hasher;
+mod hashmap;
+mod hashset;
+mod sharded;
+mod storage;
+mod timeout;
+
+pub use crate::builder::Builder;
+pub use crate::cursor::{CursorPosition, MapCursor, SetCursor};
+pub use crate::error::{ConfigError, ForeignKeyError, Timeout};
+pub use crate::finemap::TFineHashMap;
+pub use crate::hasher::FixedState;
+pub use crate::hashmap::{BucketView, Entry, Keys, MapIter, THashMap, UpdateOutcome, Values};
+pub use crate::hashset::{SetIter, THashSet};
+pub use crate::sharded::{ResizePolicy, Shard, Sharded};
+pub use crate::timeout::atomically_with_timeout;
+
+use std::hash::{BuildHasher, Hash};
+use std::thread;
+
+/// Number of buckets per available hardware thread used by constructors that do not take an
+/// explicit bucket count.
+const BUCKETS_PER_THREAD: usize = 4;
+
+/// Number of buckets used by constructors that do not take an explicit bucket count.
+///
+/// Scales with the available parallelism so that concurrent transactions rarely touch the same
+/// bucket.
+fn default_bucket_count() -> usize {
+    thread::available_parallelism()
+        .map(|threads| threads.get() * BUCKETS_PER_THREAD)
+        .unwrap_or(BUCKETS_PER_THREAD)
 }
 
-impl<T> THashSet<T>
+/// Computes the index of the bucket responsible for `key`.
+fn bucket_index<Q, S>(hash_builder: &S, key: &Q, bucket_count: usize) -> usize
 where
-    T: Any + Clone + Eq + Hash + Send + Sync,
-{
-    /// Creates a new transaction-ready HashSet with the given number of buckets.
-    pub fn new(bucket_count: usize) -> Self {
-        let mut hs = Vec::with_capacity(bucket_count);
-        for _ in 0..bucket_count {
-            hs.push(TVar::new(HashSet::new()));
-        }
-
-        THashSet { contents: hs }
-    }
-
-    /// Adds a value to the set.
-    ///
-    /// If the set did not have this value present, `true` is returned. If the value has been
-    /// present before, `false` is returned.
-    ///
-    /// This function must be called inside a `atomically` block.
-    pub fn insert(&self, trans: &mut Transaction, value: T) -> StmResult<bool> {
-        let mut hasher = DefaultHasher::new();
-        value.hash(&mut hasher);
-        let bucket_no: usize = hasher.finish() as usize % self.contents.len();
-
-        let set_ro = self.contents[bucket_no].read_ref_atomic().downcast::<HashSet<T>>().unwrap();
-
-        if set_ro.contains(&value) {
-            // nothing to be inserted, no change to hashset made
-            Ok(false)
-        } else {
-            // the element is indeed to be inserted -- write back
-            self.contents[bucket_no].modify(trans, |mut hs| { hs.insert(value); hs })?;
-            Ok(true)
-        }
-    }
-
-    /// Empties the HashSet and returns all elements as `VecDequeue`.
-    ///
-    /// Must be executed as part of a transaction. After calling this function, `self` may be
-    /// dropped, as it is empty.
-    pub fn as_vec(&self, trans: &mut Transaction) -> StmResult<Vec<T>> {
-        let mut result = Vec::new();
-
-        for set in &self.contents {
-            let mut inner_set = set.read(trans)?;
-            result.append(&mut inner_set.drain().collect());
-        }
-
-        Ok(result)
-    }
-}
-
-/// A transaction-ready hash map with a configurable number of buckets
-#[derive(Clone)]
-pub struct THashMap<K,V> {
-    contents: Vec<TVar<HashMap<K,V>>>,
-}
-
-impl<K, V> THashMap<K, V> where
-    K: Any + Clone + Eq + Hash + Send + Sync,
-    V: Any + Clone + Send + Sync
+    Q: ?Sized + Hash,
+    S: BuildHasher,
 {
-    /// Creates a new transaction-ready HashMap with the given number of buckets.
-    pub fn new(bucket_count: usize) -> Self {
-        let mut hs = Vec::with_capacity(bucket_count);
-        for _ in 0..bucket_count {
-            hs.push(TVar::new(HashMap::new()));
-        }
-
-        THashMap { contents: hs }
-    }
-
-    /// Shorthand for more efficient population of a HashMap with data
-    pub fn from_hashmap(map: HashMap<K, V>, bucket_count: usize) -> Self {
-        let estimated_size = map.len() / bucket_count;
-        let mut hs: Vec<HashMap<K, V>> = vec![HashMap::with_capacity(estimated_size); bucket_count];
-
-        for (k, v) in map.into_iter() {
-            let mut hasher = DefaultHasher::new();
-            k.hash(&mut hasher);
-            let bucket_no: usize = hasher.finish() as usize % bucket_count;
-
-            hs[bucket_no].insert(k, v);
-        }
-
-        THashMap { contents: hs.into_iter().map(TVar::new).collect() }
-    }
-
-    pub fn get_bucket(&self, item: &K) -> &TVar<HashMap<K, V>> {
-        let mut hasher = DefaultHasher::new();
-        item.hash(&mut hasher);
-        let bucket_no: usize = hasher.finish() as usize % self.contents.len();
-
-        &self.contents[bucket_no]
-    }
-
-    pub fn is_empty(&self, trans: &mut Transaction) -> StmResult<bool> {
-        for bucket in &self.contents {
-            let content = bucket.read(trans)?;
-            if !content.is_empty() {
-                return Ok(false)
-            }
-        }
-
-        Ok(true)
-    }
-
-    pub fn get_contents(&self) -> HashMap<K,V> {
-        self.contents.iter().map(TVar::read_atomic).flatten().collect()
-    }
+    hash_builder.hash_one(key) as usize % bucket_count
 }
-
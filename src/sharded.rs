@@ -0,0 +1,514 @@
+use std::any::Any;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+use std::thread;
+use stm::{atomically, StmResult, TVar, Transaction};
+
+use crate::{bucket_index, Builder, ConfigError};
+
+/// Controls when the bucket array of a container is grown or shrunk.
+///
+/// The load factor is the average number of elements per bucket. Since computing it requires
+/// reading every bucket, it is only checked when a single bucket strongly deviates from the
+/// configured bounds: when an insertion leaves the affected bucket with more than twice
+/// `max_load` elements, or a removal leaves it with fewer than half of `min_load` elements. Only
+/// the transactions performing such a check add every bucket to their read set.
+///
+/// Growing doubles the number of buckets, shrinking halves it, but never below the bucket count
+/// the container was built with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResizePolicy {
+    max_load: usize,
+    min_load: usize,
+}
+
+impl ResizePolicy {
+    /// Creates a policy growing the container when the load factor exceeds `max_load` and
+    /// shrinking it when the load factor drops below a quarter of that.
+    pub fn new(max_load: usize) -> Self {
+        ResizePolicy {
+            max_load,
+            min_load: max_load / 4,
+        }
+    }
+
+    /// Sets the load factor below which the container is shrunk. A value of zero disables
+    /// shrinking.
+    pub fn shrink_below(mut self, min_load: usize) -> Self {
+        self.min_load = min_load;
+        self
+    }
+
+    /// Returns the load factor above which the container is grown.
+    pub fn max_load(&self) -> usize {
+        self.max_load
+    }
+
+    /// Returns the load factor below which the container is shrunk.
+    pub fn min_load(&self) -> usize {
+        self.min_load
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_load == 0 || self.min_load >= self.max_load {
+            Err(ConfigError::InvalidResizePolicy)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Collection type that can be used as a single bucket of a `Sharded` container.
+///
+/// Implemented for the `HashSet` and `HashMap` of the standard library and, with the `persistent`
+/// feature, for those of `im`. Implement it for other collections, e.g. sorted vectors or
+/// bitmaps, to build further sharded containers. Elements are assigned to buckets by hashing
+/// their key, and a resize moves them by draining the old buckets through `IntoIterator` and
+/// feeding them into the new ones through `Extend`.
+pub trait Shard:
+    Any + Clone + Default + Send + Sync + IntoIterator + Extend<<Self as IntoIterator>::Item>
+{
+    /// The part of an element that determines its bucket.
+    type Key: ?Sized + Hash;
+
+    /// Returns the part of `item` that determines its bucket.
+    fn key(item: &Self::Item) -> &Self::Key;
+
+    /// Returns the number of elements in the bucket.
+    fn len(&self) -> usize;
+
+    /// Returns `true` if the bucket holds no elements.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A bucket array that is itself held in a `TVar`, so that it can be replaced by a larger or
+/// smaller one while other transactions are running. This is the building block behind
+/// `THashSet`, `THashMap` and `TFineHashMap`: it routes keys to buckets (the shards), applies
+/// the `ResizePolicy` and maintains the optional element counter.
+///
+/// Every access reads the array through the transaction, so a resize conflicts with all
+/// concurrent transactions on the container and is therefore never observed half-way. Cloning
+/// returns a handle to the same buckets.
+///
+/// A container built on `Sharded` looks up the responsible bucket, modifies a copy of it and
+/// writes it back through `store`, followed by `inserted` or `removed` to give the resize policy
+/// a chance to act:
+///
+/// ```
+/// use std::collections::BTreeSet;
+/// use stm::{atomically, StmResult, Transaction};
+/// use stm_datastructures::{Builder, Shard, Sharded};
+///
+/// #[derive(Clone, Default)]
+/// struct Sorted(BTreeSet<u64>);
+///
+/// impl IntoIterator for Sorted {
+///     type Item = u64;
+///     type IntoIter = std::collections::btree_set::IntoIter<u64>;
+///
+///     fn into_iter(self) -> Self::IntoIter {
+///         self.0.into_iter()
+///     }
+/// }
+///
+/// impl Extend<u64> for Sorted {
+///     fn extend<I: IntoIterator<Item = u64>>(&mut self, iter: I) {
+///         self.0.extend(iter)
+///     }
+/// }
+///
+/// impl Shard for Sorted {
+///     type Key = u64;
+///
+///     fn key(item: &u64) -> &u64 {
+///         item
+///     }
+///
+///     fn len(&self) -> usize {
+///         self.0.len()
+///     }
+/// }
+///
+/// fn insert(sharded: &Sharded<Sorted>, trans: &mut Transaction, value: u64) -> StmResult<bool> {
+///     let bucket = sharded.bucket_for(trans, &value)?;
+///     let mut content = bucket.read(trans)?;
+///     let len_before = content.len();
+///     if !content.0.insert(value) {
+///         return Ok(false);
+///     }
+///
+///     let len = content.len();
+///     sharded.store(trans, &bucket, len_before, content)?;
+///     sharded.inserted(trans, len)?;
+///     Ok(true)
+/// }
+///
+/// let sharded: Sharded<Sorted> = Builder::new().buckets(8).build_sharded().unwrap();
+/// assert!(atomically(|trans| insert(&sharded, trans, 42)));
+/// assert!(!atomically(|trans| insert(&sharded, trans, 42)));
+/// assert_eq!(atomically(|trans| sharded.len(trans)), 1);
+/// ```
+#[derive(Clone)]
+pub struct Sharded<C, S = RandomState> {
+    array: TVar<Arc<Vec<TVar<C>>>>,
+    hash_builder: Arc<S>,
+    policy: Option<ResizePolicy>,
+    min_buckets: usize,
+    counter: Option<LenCounter>,
+}
+
+impl<C> Sharded<C, RandomState>
+where
+    C: Shard,
+{
+    /// Creates empty buckets. Use the `Builder` to configure the hasher, a resize policy or an
+    /// element counter.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket_count` is zero.
+    pub fn new(bucket_count: usize) -> Self {
+        Builder::new()
+            .buckets(bucket_count)
+            .build_sharded()
+            .unwrap_or_else(|e| panic!("cannot create Sharded: {}", e))
+    }
+}
+
+impl<C, S> Sharded<C, S>
+where
+    C: Shard,
+    S: BuildHasher,
+{
+    /// Wraps pre-populated buckets. The caller is responsible for placing every element in the
+    /// bucket `bucket_index` assigns to it.
+    ///
+    /// If `counter_shards` is given, the number of elements is tracked in that many counters.
+    pub(crate) fn from_parts(
+        buckets: Vec<C>,
+        hash_builder: S,
+        policy: Option<ResizePolicy>,
+        counter_shards: Option<usize>,
+    ) -> Self {
+        debug_assert!(!buckets.is_empty());
+
+        let len = buckets.iter().map(Shard::len).sum();
+        Sharded {
+            min_buckets: buckets.len(),
+            array: TVar::new(Arc::new(buckets.into_iter().map(TVar::new).collect())),
+            hash_builder: Arc::new(hash_builder),
+            policy,
+            counter: counter_shards.map(|shards| LenCounter::new(shards, len)),
+        }
+    }
+
+    /// Returns a reference to the `BuildHasher` used to route keys to buckets.
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+
+    /// Returns `true` if `other` routes keys with the very same hasher, i.e. if one of them is a
+    /// handle or a deep clone of the other. Equal bucket counts then imply equal bucket indices.
+    pub(crate) fn shares_hasher<C2, S2>(&self, other: &Sharded<C2, S2>) -> bool {
+        // two live allocations never share an address, so equal pointers mean the same hasher
+        let own = Arc::as_ptr(&self.hash_builder) as *const ();
+        own == Arc::as_ptr(&other.hash_builder) as *const ()
+    }
+
+    /// Returns the current number of buckets.
+    pub fn bucket_count(&self, trans: &mut Transaction) -> StmResult<usize> {
+        Ok(self.read(trans)?.len())
+    }
+
+    /// Returns the current bucket array.
+    pub fn read(&self, trans: &mut Transaction) -> StmResult<Arc<Vec<TVar<C>>>> {
+        self.array.read(trans)
+    }
+
+    /// Returns the bucket responsible for the given key.
+    pub fn bucket_for<Q>(&self, trans: &mut Transaction, key: &Q) -> StmResult<TVar<C>>
+    where
+        Q: ?Sized + Hash,
+    {
+        let (buckets, idx) = self.locate(trans, key)?;
+        Ok(buckets[idx].clone())
+    }
+
+    /// Returns the current bucket array and the index of the bucket responsible for the given key.
+    pub fn locate<Q>(&self, trans: &mut Transaction, key: &Q) -> StmResult<(Arc<Vec<TVar<C>>>, usize)>
+    where
+        Q: ?Sized + Hash,
+    {
+        let buckets = self.read(trans)?;
+        let idx = bucket_index(self.hasher(), key, buckets.len());
+        Ok((buckets, idx))
+    }
+
+    /// Returns an iterator over all elements which reads the buckets one by one.
+    pub(crate) fn iter<'a>(&'a self, trans: &'a mut Transaction) -> BucketIter<'a, C> {
+        BucketIter {
+            trans,
+            array: &self.array,
+            buckets: None,
+            next_bucket: 0,
+            current: None,
+            done: false,
+        }
+    }
+
+    /// Creates an independent bucket array with the same layout, hasher and policy, holding the
+    /// buckets as returned by `copy`. `copy` only has to duplicate `TVar`s nested in a bucket;
+    /// the bucket itself has already been cloned.
+    pub fn deep_clone<F>(&self, trans: &mut Transaction, mut copy: F) -> StmResult<Self>
+    where
+        S: Clone,
+        F: FnMut(&mut Transaction, C) -> StmResult<C>,
+    {
+        let mut buckets = Vec::new();
+        for bucket in self.read(trans)?.iter() {
+            let content = bucket.read(trans)?;
+            buckets.push(TVar::new(copy(trans, content)?));
+        }
+
+        let counter = match &self.counter {
+            Some(counter) => Some(LenCounter::new(counter.shards.len(), counter.sum(trans)?)),
+            None => None,
+        };
+
+        Ok(Sharded {
+            array: TVar::new(Arc::new(buckets)),
+            hash_builder: Arc::clone(&self.hash_builder),
+            policy: self.policy,
+            min_buckets: self.min_buckets,
+            counter,
+        })
+    }
+
+    /// Returns the elements of up to `count` buckets starting at bucket `start`, together with the
+    /// current number of buckets.
+    pub fn read_chunk(&self, trans: &mut Transaction, start: usize, count: usize) -> StmResult<(Vec<C::Item>, usize)> {
+        let buckets = self.read(trans)?;
+        let mut items = Vec::new();
+
+        for bucket in buckets.iter().skip(start).take(count) {
+            items.extend(bucket.read(trans)?);
+        }
+
+        Ok((items, buckets.len()))
+    }
+
+    /// Returns all elements in a single transaction. Must not be called inside a transaction.
+    ///
+    /// Handles to the same buckets may still exist, so the elements are copied out of a
+    /// consistent snapshot rather than moved.
+    pub fn into_items(self) -> Vec<C::Item> {
+        atomically(|trans| self.read_chunk(trans, 0, usize::MAX)).0
+    }
+
+    /// Writes new contents to `bucket`, which held `len_before` elements. Every write that may
+    /// change the number of elements in a bucket must go through this function, so that the
+    /// element counter stays accurate.
+    pub fn store(&self, trans: &mut Transaction, bucket: &TVar<C>, len_before: usize, content: C) -> StmResult<()> {
+        if let Some(counter) = &self.counter {
+            counter.add(trans, content.len() as isize - len_before as isize)?;
+        }
+        bucket.write(trans, content)
+    }
+
+    /// Returns the total number of elements. Reads the element counter if there is one, and
+    /// every bucket otherwise.
+    pub fn len(&self, trans: &mut Transaction) -> StmResult<usize> {
+        if let Some(counter) = &self.counter {
+            return counter.sum(trans);
+        }
+
+        let mut len = 0;
+        for bucket in self.read(trans)?.iter() {
+            len += bucket.read(trans)?.len();
+        }
+
+        Ok(len)
+    }
+
+    /// Returns `true` if there are no elements. Without an element counter, reading stops at the
+    /// first non-empty bucket.
+    pub fn is_empty(&self, trans: &mut Transaction) -> StmResult<bool> {
+        if let Some(counter) = &self.counter {
+            return Ok(counter.sum(trans)? == 0);
+        }
+
+        for bucket in self.read(trans)?.iter() {
+            if !bucket.read(trans)?.is_empty() {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Replaces the bucket array by one with `bucket_count` buckets and redistributes all
+    /// elements.
+    pub fn resize(&self, trans: &mut Transaction, bucket_count: usize) -> StmResult<()> {
+        assert!(bucket_count > 0, "{}", ConfigError::ZeroBuckets);
+
+        let old = self.read(trans)?;
+        if old.len() == bucket_count {
+            return Ok(());
+        }
+
+        let mut buckets: Vec<C> = (0..bucket_count).map(|_| C::default()).collect();
+        for bucket in old.iter() {
+            for item in bucket.read(trans)? {
+                let idx = bucket_index(self.hasher(), C::key(&item), bucket_count);
+                buckets[idx].extend(Some(item));
+            }
+        }
+
+        self.array
+            .write(trans, Arc::new(buckets.into_iter().map(TVar::new).collect()))
+    }
+
+    /// Must be called after an insertion left a bucket with `bucket_len` elements. Grows the
+    /// bucket array if the resize policy asks for it.
+    pub fn inserted(&self, trans: &mut Transaction, bucket_len: usize) -> StmResult<()> {
+        let policy = match self.policy {
+            Some(policy) if bucket_len > policy.max_load.saturating_mul(2) => policy,
+            _ => return Ok(()),
+        };
+
+        let bucket_count = self.read(trans)?.len();
+        if self.len(trans)? > policy.max_load.saturating_mul(bucket_count) {
+            self.resize(trans, bucket_count * 2)?;
+        }
+
+        Ok(())
+    }
+
+    /// Must be called after a removal left a bucket with `bucket_len` elements. Shrinks the
+    /// bucket array if the resize policy asks for it.
+    pub fn removed(&self, trans: &mut Transaction, bucket_len: usize) -> StmResult<()> {
+        let policy = match self.policy {
+            Some(policy) if bucket_len.saturating_mul(2) < policy.min_load => policy,
+            _ => return Ok(()),
+        };
+
+        let bucket_count = self.read(trans)?.len();
+        let target = (bucket_count / 2).max(self.min_buckets);
+        if target < bucket_count && self.len(trans)? < policy.min_load.saturating_mul(bucket_count) {
+            self.resize(trans, target)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Number of elements in a container, split over several `TVar`s.
+///
+/// Every thread updates the shard picked by its id, so concurrent writers on different threads
+/// usually do not conflict on the counter. Reading the total has to read all shards. A single
+/// shard may become negative when elements are removed by another thread than the one that
+/// inserted them; only the sum is meaningful.
+#[derive(Clone)]
+struct LenCounter {
+    shards: Vec<TVar<isize>>,
+    hash_builder: RandomState,
+}
+
+impl LenCounter {
+    fn new(shards: usize, len: usize) -> Self {
+        debug_assert!(shards > 0);
+
+        // the initial elements are all accounted to the first shard
+        LenCounter {
+            shards: (0..shards)
+                .map(|i| TVar::new(if i == 0 { len as isize } else { 0 }))
+                .collect(),
+            hash_builder: RandomState::new(),
+        }
+    }
+
+    fn add(&self, trans: &mut Transaction, delta: isize) -> StmResult<()> {
+        if delta == 0 {
+            return Ok(());
+        }
+
+        let idx = bucket_index(&self.hash_builder, &thread::current().id(), self.shards.len());
+        let shard = &self.shards[idx];
+        let value = shard.read(trans)?;
+        shard.write(trans, value + delta)
+    }
+
+    fn sum(&self, trans: &mut Transaction) -> StmResult<usize> {
+        let mut sum = 0;
+        for shard in &self.shards {
+            sum += shard.read(trans)?;
+        }
+
+        Ok(sum as usize)
+    }
+}
+
+/// Iterator over the elements of a bucket array. Every bucket is only read once the iteration
+/// reaches it, so stopping early keeps the remaining buckets out of the read set.
+pub(crate) struct BucketIter<'a, C>
+where
+    C: Shard,
+{
+    trans: &'a mut Transaction,
+    array: &'a TVar<Arc<Vec<TVar<C>>>>,
+    buckets: Option<Arc<Vec<TVar<C>>>>,
+    next_bucket: usize,
+    current: Option<C::IntoIter>,
+    done: bool,
+}
+
+impl<'a, C> BucketIter<'a, C>
+where
+    C: Shard,
+{
+    /// Reads the next non-empty bucket. Returns `Ok(false)` once all buckets have been read.
+    fn advance(&mut self) -> StmResult<bool> {
+        if self.buckets.is_none() {
+            self.buckets = Some(self.array.read(self.trans)?);
+        }
+        let buckets = self.buckets.as_ref().unwrap();
+
+        match buckets.get(self.next_bucket) {
+            Some(bucket) => {
+                self.current = Some(bucket.read(self.trans)?.into_iter());
+                self.next_bucket += 1;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+impl<'a, C> Iterator for BucketIter<'a, C>
+where
+    C: Shard,
+{
+    type Item = StmResult<C::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            if let Some(item) = self.current.as_mut().and_then(Iterator::next) {
+                return Some(Ok(item));
+            }
+
+            match self.advance() {
+                Ok(true) => {}
+                Ok(false) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,208 @@
+//! The collection types used as buckets.
+//!
+//! By default, buckets are the hash containers of the standard library. Every write to a bucket
+//! clones it, which becomes the dominating cost once buckets hold more than a few hundred
+//! elements. With the `persistent` feature, buckets are persistent hash array mapped tries from
+//! the [im](https://crates.io/crates/im) crate instead, which share structure between versions so
+//! that cloning is O(1) and a modification costs O(log n).
+
+use std::any::Any;
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::sharded::Shard;
+
+// The bucket types are not part of the public API: features are unified across all users of
+// this crate, so a public type that changes with `persistent` would break crates that did not
+// ask for it. The deprecated `get_bucket` of the `raw-buckets` feature does hand out map
+// buckets, so that feature keeps the std map buckets even if `persistent` is enabled.
+
+/// The collection holding the values of a single `THashSet` bucket.
+#[cfg(not(feature = "persistent"))]
+pub(crate) type SetBucket<T> = HashSet<T>;
+
+/// The collection holding the values of a single `THashSet` bucket.
+#[cfg(feature = "persistent")]
+pub(crate) type SetBucket<T> = im::HashSet<T>;
+
+/// The collection holding the entries of a single `THashMap` bucket.
+#[cfg(any(not(feature = "persistent"), feature = "raw-buckets"))]
+pub(crate) type MapBucket<K, V> = HashMap<K, V>;
+
+/// The collection holding the entries of a single `THashMap` bucket.
+#[cfg(all(feature = "persistent", not(feature = "raw-buckets")))]
+pub(crate) type MapBucket<K, V> = im::HashMap<K, V>;
+
+/// Set operations whose signatures differ between the supported bucket types.
+pub(crate) trait SetStorage<T>: Shard<Item = T> {
+    /// Creates an empty bucket with room for `capacity` values, if the type supports that.
+    fn with_capacity(capacity: usize) -> Self;
+
+    /// Adds a value, returning whether it was newly inserted.
+    fn insert_value(&mut self, value: T) -> bool;
+
+    /// Removes and returns the value equal to the given one.
+    fn take_value<Q>(&mut self, value: &Q) -> Option<T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq;
+}
+
+/// Map operations whose signatures differ between the supported bucket types.
+pub(crate) trait MapStorage<K, V>: Shard<Item = (K, V)> {
+    /// Creates an empty bucket with room for `capacity` entries, if the type supports that.
+    fn with_capacity(capacity: usize) -> Self;
+
+    /// Removes a key, returning the stored key and value.
+    fn remove_key_value<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq;
+}
+
+impl<T> Shard for HashSet<T>
+where
+    T: Any + Clone + Eq + Hash + Send + Sync,
+{
+    type Key = T;
+
+    fn key(item: &T) -> &T {
+        item
+    }
+
+    fn len(&self) -> usize {
+        HashSet::len(self)
+    }
+}
+
+impl<K, V> Shard for HashMap<K, V>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+{
+    type Key = K;
+
+    fn key(item: &(K, V)) -> &K {
+        &item.0
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+}
+
+impl<T> SetStorage<T> for HashSet<T>
+where
+    T: Any + Clone + Eq + Hash + Send + Sync,
+{
+    fn with_capacity(capacity: usize) -> Self {
+        HashSet::with_capacity(capacity)
+    }
+
+    fn insert_value(&mut self, value: T) -> bool {
+        self.insert(value)
+    }
+
+    fn take_value<Q>(&mut self, value: &Q) -> Option<T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.take(value)
+    }
+}
+
+impl<K, V> MapStorage<K, V> for HashMap<K, V>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+{
+    fn with_capacity(capacity: usize) -> Self {
+        HashMap::with_capacity(capacity)
+    }
+
+    fn remove_key_value<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.remove_entry(key)
+    }
+}
+
+#[cfg(feature = "persistent")]
+impl<T> Shard for im::HashSet<T>
+where
+    T: Any + Clone + Eq + Hash + Send + Sync,
+{
+    type Key = T;
+
+    fn key(item: &T) -> &T {
+        item
+    }
+
+    fn len(&self) -> usize {
+        im::HashSet::len(self)
+    }
+}
+
+#[cfg(feature = "persistent")]
+impl<K, V> Shard for im::HashMap<K, V>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+{
+    type Key = K;
+
+    fn key(item: &(K, V)) -> &K {
+        &item.0
+    }
+
+    fn len(&self) -> usize {
+        im::HashMap::len(self)
+    }
+}
+
+#[cfg(feature = "persistent")]
+impl<T> SetStorage<T> for im::HashSet<T>
+where
+    T: Any + Clone + Eq + Hash + Send + Sync,
+{
+    fn with_capacity(_capacity: usize) -> Self {
+        // tries grow node by node, there is nothing to preallocate
+        im::HashSet::new()
+    }
+
+    fn insert_value(&mut self, value: T) -> bool {
+        self.insert(value).is_none()
+    }
+
+    fn take_value<Q>(&mut self, value: &Q) -> Option<T>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.remove(value)
+    }
+}
+
+#[cfg(feature = "persistent")]
+impl<K, V> MapStorage<K, V> for im::HashMap<K, V>
+where
+    K: Any + Clone + Eq + Hash + Send + Sync,
+    V: Any + Clone + Send + Sync,
+{
+    fn with_capacity(_capacity: usize) -> Self {
+        // tries grow node by node, there is nothing to preallocate
+        im::HashMap::new()
+    }
+
+    fn remove_key_value<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.remove_with_key(key)
+    }
+}
@@ -0,0 +1,51 @@
+use std::cmp;
+use std::thread;
+use std::time::{Duration, Instant};
+use stm::{atomically, StmResult, Transaction};
+
+use crate::Timeout;
+
+/// Initial pause between two attempts of a retrying transaction.
+const MIN_BACKOFF: Duration = Duration::from_micros(50);
+
+/// Upper bound for the pause between two attempts, which is also the worst-case delay between a
+/// commit that unblocks the transaction and the transaction noticing it.
+const MAX_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Runs a transaction like `stm::atomically`, but gives up after `timeout`.
+///
+/// Whenever `f` calls `stm::retry` (e.g. through `THashSet::pop_any` on an empty set), the
+/// transaction is abandoned and attempted again after a short, exponentially growing pause. If it
+/// still retries once `timeout` has passed, `Err(Timeout)` is returned, so that service threads
+/// get the chance to check for shutdown requests instead of blocking forever. A timeout too large
+/// to be represented as a point in time (e.g. `Duration::MAX`) never expires.
+///
+/// Must not be called inside a transaction.
+pub fn atomically_with_timeout<T, F>(timeout: Duration, f: F) -> Result<T, Timeout>
+where
+    F: Fn(&mut Transaction) -> StmResult<T>,
+{
+    let deadline = Instant::now().checked_add(timeout);
+    let mut backoff = MIN_BACKOFF;
+
+    loop {
+        let result = atomically(|trans| trans.or(|t| f(t).map(Some), |_| Ok(None)));
+        if let Some(value) = result {
+            return Ok(value);
+        }
+
+        let pause = match deadline {
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(Timeout);
+                }
+                cmp::min(backoff, deadline - now)
+            }
+            None => backoff,
+        };
+
+        thread::sleep(pause);
+        backoff = cmp::min(backoff * 2, MAX_BACKOFF);
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use stm::atomically;
+use stm_datastructures::{THashMap, THashSet};
+
+#[test]
+fn pop_any_hands_out_every_value_once() {
+    let bag = Arc::new(THashSet::new(4));
+
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let bag = Arc::clone(&bag);
+            thread::spawn(move || {
+                let mut taken = Vec::new();
+                loop {
+                    let value = atomically(|trans| bag.pop_any(trans));
+                    if value == u32::MAX {
+                        return taken;
+                    }
+                    taken.push(value);
+                }
+            })
+        })
+        .collect();
+
+    // give the workers time to block on the empty set
+    thread::sleep(Duration::from_millis(50));
+    for i in 0..1000 {
+        atomically(|trans| bag.insert(trans, i));
+    }
+    // one termination marker per worker, inserted once the work is done
+    for _ in 0..4 {
+        atomically(|trans| {
+            if bag.is_empty(trans)? {
+                bag.insert(trans, u32::MAX)
+            } else {
+                stm::retry()
+            }
+        });
+    }
+
+    let mut all: Vec<u32> = workers.into_iter().flat_map(|w| w.join().unwrap()).collect();
+    all.sort_unstable();
+    assert_eq!(all, (0..1000).collect::<Vec<_>>());
+}
+
+#[test]
+fn wait_for_key_and_remove_blocking() {
+    let map = Arc::new(THashMap::new(4));
+
+    let waiter = {
+        let map = Arc::clone(&map);
+        thread::spawn(move || {
+            let seen = atomically(|trans| map.wait_for_key(trans, &1));
+            let removed = atomically(|trans| map.remove_blocking(trans, &2));
+            (seen, removed)
+        })
+    };
+
+    thread::sleep(Duration::from_millis(20));
+    atomically(|trans| map.insert(trans, 1, "one"));
+    thread::sleep(Duration::from_millis(20));
+    atomically(|trans| map.insert(trans, 2, "two"));
+
+    assert_eq!(waiter.join().unwrap(), ("one", "two"));
+    assert!(!atomically(|trans| map.contains_key(trans, &2)));
+}
+
+#[test]
+fn try_pop_any_does_not_block() {
+    let set = THashSet::new(4);
+    assert_eq!(atomically(|trans| set.try_pop_any(trans)), None);
+
+    atomically(|trans| set.insert(trans, 3));
+    assert_eq!(atomically(|trans| set.try_pop_any(trans)), Some(3));
+    assert!(atomically(|trans| set.is_empty(trans)));
+}
+
+#[test]
+fn timeout_expires_and_succeeds() {
+    use stm_datastructures::{atomically_with_timeout, Timeout};
+
+    let set: Arc<THashSet<u32>> = Arc::new(THashSet::new(4));
+    assert_eq!(
+        atomically_with_timeout(Duration::from_millis(20), |trans| set.pop_any(trans)),
+        Err(Timeout)
+    );
+
+    let producer = {
+        let set = Arc::clone(&set);
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            atomically(|trans| set.insert(trans, 7));
+        })
+    };
+    assert_eq!(
+        atomically_with_timeout(Duration::from_secs(10), |trans| set.pop_any(trans)),
+        Ok(7)
+    );
+    producer.join().unwrap();
+}
+
+#[test]
+fn huge_timeout_never_expires() {
+    use stm_datastructures::atomically_with_timeout;
+
+    let set: THashSet<u32> = THashSet::new(4);
+    atomically(|trans| set.insert(trans, 3));
+    assert_eq!(atomically_with_timeout(Duration::MAX, |trans| set.pop_any(trans)), Ok(3));
+}
@@ -0,0 +1,100 @@
+use std::collections::{HashMap, HashSet};
+
+use stm::atomically;
+use stm_datastructures::{Builder, ConfigError, FixedState, Sharded, THashMap, THashSet};
+
+#[test]
+fn zero_buckets_are_rejected() {
+    assert_eq!(THashSet::<u32>::try_new(0).err(), Some(ConfigError::ZeroBuckets));
+    assert_eq!(THashMap::<u32, u32>::try_new(0).err(), Some(ConfigError::ZeroBuckets));
+    assert_eq!(
+        Builder::new().buckets(0).build_map_from(HashMap::<u32, u32>::new()).err(),
+        Some(ConfigError::ZeroBuckets)
+    );
+}
+
+#[test]
+#[should_panic(expected = "bucket count")]
+fn new_with_zero_buckets_panics() {
+    THashSet::<u32>::new(0);
+}
+
+#[test]
+fn default_containers_are_usable() {
+    let set: THashSet<u32> = THashSet::default();
+    let map: THashMap<u32, u32> = THashMap::default();
+
+    assert!(atomically(|trans| set.insert(trans, 1)));
+    assert_eq!(atomically(|trans| map.insert(trans, 1, 2)), None);
+}
+
+#[test]
+fn builder_from_map() {
+    let source: HashMap<u32, u32> = (0..100).map(|i| (i, i * 2)).collect();
+    let map = Builder::new()
+        .buckets(7)
+        .hasher(FixedState::with_seed(3))
+        .build_map_from(source.clone())
+        .unwrap();
+
+    assert_eq!(map.get_contents(), source);
+    assert_eq!(atomically(|trans| map.get(trans, &21)), Some(42));
+}
+
+#[test]
+fn len_counter_tracks_all_modifications() {
+    let set: THashSet<u32> = Builder::new().buckets(4).len_counter(3).build_set_from(0..10).unwrap();
+    assert_eq!(atomically(|trans| set.len(trans)), 10);
+
+    atomically(|trans| {
+        set.insert(trans, 10)?;
+        set.remove(trans, &0)?;
+        set.retain(trans, |v| v % 2 == 0)
+    });
+    assert_eq!(atomically(|trans| set.len(trans)), 5);
+
+    let copy = atomically(|trans| set.deep_clone(trans));
+    atomically(|trans| set.clear(trans));
+    assert!(atomically(|trans| set.is_empty(trans)));
+    assert_eq!(atomically(|trans| copy.len(trans)), 5);
+
+    let map: THashMap<u32, u32> = Builder::new().buckets(4).len_counter(2).build_map().unwrap();
+    let threads: Vec<_> = (0..4)
+        .map(|t| {
+            let map = map.clone();
+            std::thread::spawn(move || {
+                for i in 0..100 {
+                    atomically(|trans| map.insert(trans, t * 100 + i, i));
+                }
+                for i in 0..50 {
+                    atomically(|trans| map.entry(trans, t * 100 + i)?.remove_entry());
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(atomically(|trans| map.len(trans)), 200);
+    assert_eq!(map.get_contents().len(), 200);
+
+    assert_eq!(
+        Builder::new().len_counter(0).build_set::<u32>().err(),
+        Some(ConfigError::ZeroCounterShards)
+    );
+}
+
+#[test]
+fn sharded_routes_items_to_their_buckets() {
+    let sharded: Sharded<HashSet<u32>, FixedState> = Builder::new()
+        .buckets(8)
+        .hasher(FixedState::with_seed(3))
+        .build_sharded_from(0..100)
+        .unwrap();
+
+    for value in 0..100 {
+        let bucket = atomically(|trans| sharded.bucket_for(trans, &value)?.read(trans));
+        assert!(bucket.contains(&value));
+    }
+    assert_eq!(atomically(|trans| sharded.len(trans)), 100);
+}
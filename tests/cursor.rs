@@ -0,0 +1,121 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use stm::atomically;
+use stm_datastructures::{CursorPosition, THashMap, THashSet};
+
+#[test]
+fn map_cursor_visits_every_entry_once() {
+    let map = THashMap::new(10);
+    atomically(|trans| {
+        for i in 0..500u32 {
+            map.insert(trans, i, i)?;
+        }
+        Ok(())
+    });
+
+    let mut cursor = map.cursor(3);
+    let mut seen = Vec::new();
+    while let Some(chunk) = cursor.next_chunk() {
+        seen.extend(chunk.into_iter().map(|(k, _)| k));
+    }
+    assert_eq!(cursor.position().index, 10);
+    assert!(!cursor.layout_changed());
+
+    seen.sort_unstable();
+    assert_eq!(seen, (0..500).collect::<Vec<_>>());
+}
+
+#[test]
+fn set_cursor_resumes() {
+    let set = THashSet::new(8);
+    atomically(|trans| {
+        for i in 0..100u32 {
+            set.insert(trans, i)?;
+        }
+        Ok(())
+    });
+
+    let mut cursor = set.cursor(2);
+    let mut seen: Vec<u32> = cursor.next_chunk().unwrap();
+    let position = cursor.position();
+    assert_eq!(position, CursorPosition { index: 2, bucket_count: Some(8) });
+
+    seen.extend(set.cursor_at(position, 4).flatten());
+    seen.sort_unstable();
+    assert_eq!(seen, (0..100).collect::<Vec<_>>());
+}
+
+#[test]
+fn cursor_makes_progress_alongside_writers() {
+    let map = Arc::new(THashMap::new(16));
+    atomically(|trans| {
+        for i in 0..1000u32 {
+            map.insert(trans, i, 0u32)?;
+        }
+        Ok(())
+    });
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let map = Arc::clone(&map);
+        let stop = Arc::clone(&stop);
+        thread::spawn(move || {
+            let mut i = 0;
+            while !stop.load(Ordering::SeqCst) {
+                atomically(|trans| {
+                    map.entry(trans, i % 1000)?
+                        .and_modify(|v| *v += 1)
+                        .map(|_| ())
+                });
+                i += 1;
+            }
+        })
+    };
+
+    // the writer neither inserts nor removes keys, so every key is visited exactly once
+    let mut seen = HashSet::new();
+    for chunk in map.cursor(1) {
+        for (k, _) in chunk {
+            assert!(seen.insert(k));
+        }
+    }
+    assert_eq!(seen.len(), 1000);
+
+    stop.store(true, Ordering::SeqCst);
+    writer.join().unwrap();
+}
+
+#[test]
+fn huge_chunks_read_the_rest() {
+    let map = THashMap::new(4);
+    atomically(|trans| map.insert_many(trans, (0..20u32).map(|i| (i, i))));
+
+    let start = CursorPosition { index: 1, bucket_count: None };
+    let mut cursor = map.cursor_at(start, usize::MAX);
+    assert!(cursor.next_chunk().is_some());
+    assert!(cursor.next_chunk().is_none());
+    assert_eq!(cursor.position().index, 4);
+}
+
+#[test]
+fn resumed_cursor_detects_resize() {
+    let map = THashMap::new(4);
+    atomically(|trans| map.insert_many(trans, (0..100u32).map(|i| (i, i))));
+
+    let mut cursor = map.cursor(2);
+    cursor.next_chunk().unwrap();
+    let position = cursor.position();
+
+    atomically(|trans| map.resize(trans, 16));
+
+    let mut resumed = map.cursor_at(position, 2);
+    resumed.next_chunk().unwrap();
+    assert!(resumed.layout_changed());
+
+    let mut unchanged = map.cursor_at(resumed.position(), 2);
+    unchanged.next_chunk().unwrap();
+    assert!(!unchanged.layout_changed());
+}
@@ -0,0 +1,167 @@
+use std::sync::Arc;
+use std::thread;
+
+use stm::atomically;
+use stm_datastructures::{Builder, ConfigError, ResizePolicy, THashMap, THashSet};
+
+#[test]
+fn explicit_resize_keeps_contents() {
+    let map = THashMap::new(2);
+    atomically(|trans| {
+        for i in 0..100 {
+            map.insert(trans, i, i * 3)?;
+        }
+        Ok(())
+    });
+
+    atomically(|trans| map.resize(trans, 17));
+    assert_eq!(atomically(|trans| map.bucket_count(trans)), 17);
+    for i in 0..100 {
+        assert_eq!(atomically(|trans| map.get(trans, &i)), Some(i * 3));
+    }
+
+    atomically(|trans| map.resize(trans, 1));
+    assert_eq!(map.get_contents().len(), 100);
+}
+
+#[test]
+fn policy_grows_and_shrinks() {
+    let set: THashSet<u32> = Builder::new()
+        .buckets(2)
+        .resize_policy(ResizePolicy::new(8).shrink_below(2))
+        .build_set()
+        .unwrap();
+
+    atomically(|trans| {
+        for i in 0..1000 {
+            set.insert(trans, i)?;
+        }
+        Ok(())
+    });
+    let grown = atomically(|trans| set.bucket_count(trans));
+    assert!(grown >= 1000 / 8, "only {} buckets", grown);
+
+    for i in 0..1000 {
+        atomically(|trans| set.remove(trans, &i));
+    }
+    assert!(atomically(|trans| set.bucket_count(trans)) < grown);
+    assert!(atomically(|trans| set.bucket_count(trans)) >= 2);
+}
+
+#[test]
+fn draining_and_clearing_shrink_the_set() {
+    let set: THashSet<u32> = Builder::new()
+        .buckets(2)
+        .resize_policy(ResizePolicy::new(8).shrink_below(2))
+        .build_set()
+        .unwrap();
+    let fill = || {
+        atomically(|trans| {
+            for i in 0..1000 {
+                set.insert(trans, i)?;
+            }
+            Ok(())
+        })
+    };
+
+    fill();
+    let grown = atomically(|trans| set.bucket_count(trans));
+    assert_eq!(atomically(|trans| set.drain(trans)).len(), 1000);
+    assert!(atomically(|trans| set.bucket_count(trans)) < grown);
+
+    fill();
+    let grown = atomically(|trans| set.bucket_count(trans));
+    atomically(|trans| set.clear(trans));
+    assert!(atomically(|trans| set.bucket_count(trans)) < grown);
+}
+
+#[test]
+fn mapping_set_values_keeps_the_counter_and_policy() {
+    let set: THashSet<u32> = Builder::new()
+        .buckets(2)
+        .resize_policy(ResizePolicy::new(8).shrink_below(2))
+        .len_counter(4)
+        .build_set()
+        .unwrap();
+    atomically(|trans| {
+        for i in 0..1000 {
+            set.insert(trans, i)?;
+        }
+        Ok(())
+    });
+    let grown = atomically(|trans| set.bucket_count(trans));
+
+    // merging all values into a handful shrinks the set
+    atomically(|trans| set.map_values_in_place(trans, |v| Some(v % 5)));
+    assert_eq!(atomically(|trans| set.len(trans)), 5);
+    assert!(atomically(|trans| set.bucket_count(trans)) < grown);
+    for i in 0..5 {
+        assert!(atomically(|trans| set.contains(trans, &i)));
+    }
+}
+
+#[test]
+fn clearing_shrinks_the_map() {
+    let map: THashMap<u32, u32> = Builder::new()
+        .buckets(2)
+        .resize_policy(ResizePolicy::new(8).shrink_below(2))
+        .build_map()
+        .unwrap();
+
+    atomically(|trans| map.insert_many(trans, (0..1000).map(|i| (i, i))));
+    let grown = atomically(|trans| map.bucket_count(trans));
+    atomically(|trans| map.clear(trans));
+    assert!(atomically(|trans| map.bucket_count(trans)) < grown);
+}
+
+#[test]
+fn huge_load_factors_do_not_overflow() {
+    let map: THashMap<u32, u32> = Builder::new()
+        .buckets(2)
+        .resize_policy(ResizePolicy::new(usize::MAX / 2 + 1).shrink_below(usize::MAX / 2))
+        .build_map()
+        .unwrap();
+
+    atomically(|trans| map.insert(trans, 1, 1));
+    atomically(|trans| map.remove(trans, &1));
+    assert_eq!(atomically(|trans| map.bucket_count(trans)), 2);
+}
+
+#[test]
+fn invalid_policy_is_rejected() {
+    let result = Builder::new()
+        .resize_policy(ResizePolicy::new(4).shrink_below(4))
+        .build_map::<u8, u8>();
+    assert_eq!(result.err(), Some(ConfigError::InvalidResizePolicy));
+}
+
+#[test]
+fn concurrent_inserts_while_growing() {
+    let map = Arc::new(
+        Builder::new()
+            .buckets(1)
+            .resize_policy(ResizePolicy::new(4))
+            .build_map()
+            .unwrap(),
+    );
+
+    let handles: Vec<_> = (0..8u32)
+        .map(|thread_no| {
+            let map = Arc::clone(&map);
+            thread::spawn(move || {
+                for i in 0..250 {
+                    let key = thread_no * 1000 + i;
+                    atomically(|trans| map.insert(trans, key, thread_no));
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let contents = map.get_contents();
+    assert_eq!(contents.len(), 8 * 250);
+    assert!(contents.iter().all(|(k, v)| k / 1000 == *v));
+}
@@ -0,0 +1,57 @@
+use std::sync::Arc;
+use std::thread;
+
+use stm::atomically;
+use stm_datastructures::TFineHashMap;
+
+#[test]
+fn insert_get_remove() {
+    let map = TFineHashMap::new(4);
+
+    assert_eq!(atomically(|trans| map.insert(trans, "a".to_string(), 1)), None);
+    assert_eq!(atomically(|trans| map.insert(trans, "a".to_string(), 2)), Some(1));
+    assert_eq!(atomically(|trans| map.get(trans, "a")), Some(2));
+    assert!(atomically(|trans| map.modify(trans, "a", |v| v * 10)));
+    assert!(!atomically(|trans| map.modify(trans, "b", |v| v * 10)));
+    assert_eq!(atomically(|trans| map.len(trans)), 1);
+
+    assert_eq!(atomically(|trans| map.remove(trans, "a")), Some(20));
+    assert!(!atomically(|trans| map.contains_key(trans, "a")));
+    assert!(atomically(|trans| map.is_empty(trans)));
+}
+
+#[test]
+fn concurrent_counters() {
+    let map = Arc::new(TFineHashMap::new(2));
+
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let map = Arc::clone(&map);
+            thread::spawn(move || {
+                for i in 0..500u32 {
+                    atomically(|trans| map.modify_or_insert(trans, i % 16, |c| c + 1, 1u32));
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let contents = map.get_contents();
+    assert_eq!(contents.len(), 16);
+    assert_eq!(contents.values().sum::<u32>(), 8 * 500);
+}
+
+#[test]
+fn deep_clone_copies_value_tvars() {
+    let map = TFineHashMap::new(4);
+    atomically(|trans| map.insert(trans, "a", 1));
+
+    let copy = atomically(|trans| map.deep_clone(trans));
+    atomically(|trans| map.insert(trans, "a", 2));
+
+    assert_eq!(atomically(|trans| map.get(trans, "a")), Some(2));
+    assert_eq!(atomically(|trans| copy.get(trans, "a")), Some(1));
+}
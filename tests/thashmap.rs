@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use stm::atomically;
+use stm_datastructures::THashMap;
+
+#[test]
+fn insert_get_remove() {
+    let map = THashMap::new(4);
+
+    assert_eq!(atomically(|trans| map.insert(trans, "a".to_string(), 1)), None);
+    assert_eq!(atomically(|trans| map.insert(trans, "a".to_string(), 2)), Some(1));
+    assert_eq!(atomically(|trans| map.get(trans, "a")), Some(2));
+    assert!(atomically(|trans| map.contains_key(trans, "a")));
+    assert!(!atomically(|trans| map.contains_key(trans, "b")));
+
+    assert_eq!(atomically(|trans| map.remove(trans, "a")), Some(2));
+    assert_eq!(atomically(|trans| map.remove(trans, "a")), None);
+    assert!(atomically(|trans| map.is_empty(trans)));
+}
+
+#[test]
+fn get_or_default_does_not_insert() {
+    let map: THashMap<u32, Vec<u32>> = THashMap::new(2);
+
+    assert_eq!(atomically(|trans| map.get_or_default(trans, &7)), Vec::new());
+    assert!(atomically(|trans| map.is_empty(trans)));
+}
+
+#[test]
+fn entry_counters_and_lists() {
+    let counters: THashMap<&str, u32> = THashMap::new(4);
+
+    for _ in 0..3 {
+        atomically(|trans| counters.entry(trans, "hits")?.and_modify(|c| *c += 1)?.or_insert(1));
+    }
+    assert_eq!(atomically(|trans| counters.get(trans, "hits")), Some(3));
+
+    let lists: THashMap<u8, Vec<u8>> = THashMap::new(2);
+    atomically(|trans| {
+        for i in 0..4 {
+            lists.entry(trans, i % 2)?.and_modify(|l| l.push(i))?.or_insert_with(|| vec![i])?;
+        }
+        Ok(())
+    });
+    assert_eq!(atomically(|trans| lists.get(trans, &0)), Some(vec![0, 2]));
+    assert_eq!(atomically(|trans| lists.get(trans, &1)), Some(vec![1, 3]));
+
+    assert_eq!(atomically(|trans| lists.entry(trans, 5)?.or_default()), Vec::new());
+    assert!(atomically(|trans| lists.contains_key(trans, &5)));
+}
+
+#[test]
+fn entry_remove() {
+    let map = THashMap::new(2);
+    atomically(|trans| map.insert(trans, 1, "one"));
+
+    assert_eq!(atomically(|trans| map.entry(trans, 1)?.remove_entry()), Some((1, "one")));
+    assert_eq!(atomically(|trans| map.entry(trans, 1)?.remove_entry()), None);
+    assert!(atomically(|trans| map.is_empty(trans)));
+}
+
+#[test]
+fn with_bucket_rejects_foreign_keys() {
+    let map = THashMap::new(8);
+
+    let (inserted, rejected) = atomically(|trans| {
+        map.with_bucket(trans, &0u32, |bucket| {
+            let mut inserted = Vec::new();
+            let mut rejected = Vec::new();
+            for k in 0..64u32 {
+                match bucket.insert(k, k * 2) {
+                    Ok(_) => inserted.push(k),
+                    Err(e) => rejected.push(e.into_inner().0),
+                }
+            }
+            (inserted, rejected)
+        })
+    });
+
+    assert!(inserted.contains(&0));
+    assert_eq!(inserted.len() + rejected.len(), 64);
+    for k in inserted {
+        assert_eq!(atomically(|trans| map.get(trans, &k)), Some(k * 2));
+    }
+    for k in rejected {
+        assert!(!atomically(|trans| map.contains_key(trans, &k)));
+    }
+}
+
+#[test]
+fn with_bucket_modifies_in_place() {
+    let map = THashMap::new(4);
+    atomically(|trans| map.insert(trans, "a", 1));
+
+    let old = atomically(|trans| {
+        map.with_bucket(trans, "a", |bucket| {
+            let value = bucket.get_mut("a").unwrap();
+            *value += 1;
+            bucket.remove("missing")
+        })
+    });
+
+    assert_eq!(old, None);
+    assert_eq!(atomically(|trans| map.get(trans, "a")), Some(2));
+}
+
+#[test]
+fn bulk_mutation() {
+    let map = THashMap::new(4);
+    atomically(|trans| {
+        for i in 0..20u32 {
+            map.insert(trans, i, i)?;
+        }
+        Ok(())
+    });
+
+    atomically(|trans| map.map_values_in_place(trans, |k, v| if k % 2 == 0 { Some(v * 10) } else { None }));
+    assert_eq!(atomically(|trans| map.get(trans, &4)), Some(40));
+    assert_eq!(atomically(|trans| map.get(trans, &5)), Some(5));
+
+    let mut drained = atomically(|trans| map.drain_filter(trans, |k, _| *k >= 15));
+    drained.sort_unstable();
+    assert_eq!(drained, vec![(15, 15), (16, 160), (17, 17), (18, 180), (19, 19)]);
+
+    atomically(|trans| map.retain(trans, |_, v| *v < 10));
+    assert_eq!(map.get_contents().len(), 6);
+
+    atomically(|trans| map.clear(trans));
+    assert!(atomically(|trans| map.is_empty(trans)));
+}
+
+#[test]
+fn iterators() {
+    let map = THashMap::new(4);
+    atomically(|trans| {
+        for i in 0..20u32 {
+            map.insert(trans, i, i * 2)?;
+        }
+        Ok(())
+    });
+
+    let mut entries: Vec<(u32, u32)> = atomically(|trans| map.iter(trans).collect());
+    entries.sort_unstable();
+    assert_eq!(entries, (0..20).map(|i| (i, i * 2)).collect::<Vec<_>>());
+
+    let key_sum: u32 = atomically(|trans| map.keys(trans).sum());
+    let value_sum: u32 = atomically(|trans| map.values(trans).sum());
+    assert_eq!(key_sum * 2, value_sum);
+
+    let found = atomically(|trans| {
+        map.iter(trans)
+            .find(|entry| entry.as_ref().map_or(true, |(k, _)| *k == 7))
+            .transpose()
+    });
+    assert_eq!(found, Some((7, 14)));
+}
+
+#[test]
+fn std_conversions() {
+    let map: THashMap<u32, u32> = (0..50).map(|i| (i, i * 2)).collect();
+    assert_eq!(atomically(|trans| map.get(trans, &21)), Some(42));
+
+    let mut map = THashMap::<&str, u32>::from(vec![("a", 1), ("b", 2), ("a", 3)]);
+    map.extend(vec![("c", 4)]);
+    let expected: HashMap<_, _> = [("a", 3), ("b", 2), ("c", 4)].iter().copied().collect();
+    assert_eq!(map.into_iter().collect::<HashMap<_, _>>(), expected);
+
+    let source: HashMap<u32, u32> = (0..10).map(|i| (i, i)).collect();
+    let map = THashMap::<u32, u32>::from(source.clone());
+    assert_eq!(map.get_contents(), source);
+}
+
+#[test]
+fn handle_and_deep_clone() {
+    let map = THashMap::new(4);
+    atomically(|trans| map.insert(trans, 1, 1));
+
+    let handle = map.handle();
+    let copy = atomically(|trans| map.deep_clone(trans));
+    atomically(|trans| map.insert(trans, 2, 2));
+
+    assert_eq!(atomically(|trans| handle.get(trans, &2)), Some(2));
+    assert_eq!(atomically(|trans| copy.get(trans, &2)), None);
+    assert_eq!(atomically(|trans| copy.get(trans, &1)), Some(1));
+    assert_eq!(
+        atomically(|trans| copy.bucket_count(trans)),
+        atomically(|trans| map.bucket_count(trans))
+    );
+}
+
+#[test]
+fn multi_key_operations() {
+    let map: THashMap<String, u32> = THashMap::new(4);
+
+    let old = atomically(|trans| {
+        map.insert_many(trans, vec![("a".to_string(), 1), ("b".to_string(), 2), ("a".to_string(), 3)])
+    });
+    assert_eq!(old, vec![None, None, Some(1)]);
+    assert_eq!(atomically(|trans| map.get_many(trans, &["a", "b", "c"])), vec![Some(3), Some(2), None]);
+
+    assert!(atomically(|trans| map.swap(trans, "a", "b")));
+    assert!(!atomically(|trans| map.swap(trans, "a", "c")));
+    assert_eq!(atomically(|trans| map.get_many(trans, &["a", "b"])), vec![Some(2), Some(3)]);
+
+    assert!(atomically(|trans| map.rename(trans, "a", "c".to_string())));
+    assert!(!atomically(|trans| map.rename(trans, "a", "d".to_string())));
+    assert!(atomically(|trans| map.rename(trans, "b", "c".to_string())));
+    assert_eq!(atomically(|trans| map.get_many(trans, &["a", "b", "c"])), vec![None, None, Some(3)]);
+    assert_eq!(atomically(|trans| map.len(trans)), 1);
+
+    atomically(|trans| map.insert_many(trans, (0..20).map(|i| (i.to_string(), i))));
+    let removed = atomically(|trans| map.remove_many(trans, &["3", "4", "x"]));
+    assert_eq!(removed, vec![Some(3), Some(4), None]);
+    assert_eq!(atomically(|trans| map.len(trans)), 19);
+}
+
+#[test]
+fn transfer_between_maps() {
+    let pending: THashMap<u32, &str> = (0..10).map(|i| (i, "record")).collect();
+    let done = THashMap::new(4);
+
+    assert!(atomically(|trans| pending.transfer_to(trans, &done, &3)));
+    assert!(!atomically(|trans| pending.transfer_to(trans, &done, &3)));
+    assert_eq!(atomically(|trans| pending.transfer_where(trans, &done, |k, _| k % 2 == 0)), 5);
+
+    assert_eq!(atomically(|trans| pending.len(trans)), 4);
+    assert_eq!(atomically(|trans| done.len(trans)), 6);
+    assert_eq!(atomically(|trans| done.remove_entry(trans, &3)), Some((3, "record")));
+}
+
+#[test]
+fn conditional_updates() {
+    use stm_datastructures::UpdateOutcome;
+
+    let map: THashMap<&str, u32> = THashMap::new(4);
+
+    assert_eq!(atomically(|trans| map.insert_if_absent(trans, "a", 1)), None);
+    assert_eq!(atomically(|trans| map.insert_if_absent(trans, "a", 2)), Some(1));
+
+    assert_eq!(atomically(|trans| map.compare_and_swap(trans, "a", &1, 5)), Ok(1));
+    assert_eq!(atomically(|trans| map.compare_and_swap(trans, "a", &1, 6)), Err(Some(5)));
+    assert_eq!(atomically(|trans| map.compare_and_swap(trans, "b", &1, 6)), Err(None));
+
+    assert_eq!(
+        atomically(|trans| map.update_if(trans, "a", |v| *v > 3, |v| v * 2)),
+        UpdateOutcome::Updated { old: 5, new: 10 }
+    );
+    assert_eq!(
+        atomically(|trans| map.update_if(trans, "a", |v| *v > 20, |v| v * 2)),
+        UpdateOutcome::Rejected(10)
+    );
+    assert_eq!(atomically(|trans| map.update_if(trans, "b", |_| true, |v| v + 1)), UpdateOutcome::Missing);
+
+    assert_eq!(atomically(|trans| map.replace(trans, "a", 0)), Some(10));
+    assert_eq!(atomically(|trans| map.replace(trans, "b", 0)), None);
+    assert!(!atomically(|trans| map.contains_key(trans, "b")));
+    assert_eq!(atomically(|trans| map.get(trans, "a")), Some(0));
+}
@@ -0,0 +1,108 @@
+//! Stress tests checking that `THashMap` operations are serializable under contention.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use stm::atomically;
+use stm_datastructures::THashMap;
+
+const KEYS: u32 = 64;
+
+/// Writers move units between keys, so the sum over all values is invariant. Every snapshot has to
+/// observe that sum.
+#[test]
+fn get_contents_is_a_consistent_cut() {
+    let map = Arc::new(THashMap::new(8));
+    atomically(|trans| {
+        for k in 0..KEYS {
+            map.insert(trans, k, 100i64)?;
+        }
+        Ok(())
+    });
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let writers: Vec<_> = (0..4)
+        .map(|thread_no| {
+            let map = Arc::clone(&map);
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                let mut i = thread_no;
+                while !stop.load(Ordering::SeqCst) {
+                    let from = i % KEYS;
+                    let to = (i * 7 + 3) % KEYS;
+                    atomically(|trans| {
+                        map.entry(trans, from)?.and_modify(|v| *v -= 1)?;
+                        map.entry(trans, to)?.and_modify(|v| *v += 1)?;
+                        Ok(())
+                    });
+                    i += 1;
+                }
+            })
+        })
+        .collect();
+
+    for _ in 0..200 {
+        let total: i64 = map.get_contents().values().sum();
+        assert_eq!(total, 100 * KEYS as i64);
+    }
+
+    stop.store(true, Ordering::SeqCst);
+    for writer in writers {
+        writer.join().unwrap();
+    }
+}
+
+/// Workers move records back and forth between two maps, one at a time and in bulk, while a
+/// checker verifies that every record is in exactly one of the maps.
+#[test]
+fn transfers_neither_lose_nor_duplicate_records() {
+    const RECORDS: u32 = 500;
+
+    let pending = Arc::new(THashMap::new(8));
+    let done = Arc::new(THashMap::new(8));
+    atomically(|trans| pending.insert_many(trans, (0..RECORDS).map(|k| (k, k * 10))));
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let workers: Vec<_> = (0..4u32)
+        .map(|thread_no| {
+            let pending = Arc::clone(&pending);
+            let done = Arc::clone(&done);
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                let mut i = thread_no;
+                while !stop.load(Ordering::SeqCst) {
+                    let key = (i * 31) % RECORDS;
+                    if thread_no % 2 == 0 {
+                        atomically(|trans| pending.transfer_to(trans, &done, &key));
+                    } else if i % 50 == 0 {
+                        atomically(|trans| done.transfer_where(trans, &pending, |k, _| k % 4 == thread_no % 4));
+                    } else {
+                        atomically(|trans| done.transfer_to(trans, &pending, &key));
+                    }
+                    i += 1;
+                }
+            })
+        })
+        .collect();
+
+    let check = || {
+        let (pending, done) = atomically(|trans| Ok((pending.snapshot(trans)?, done.snapshot(trans)?)));
+        assert_eq!(pending.len() + done.len(), RECORDS as usize);
+        for k in 0..RECORDS {
+            let value = pending.get(&k).or_else(|| done.get(&k));
+            assert_eq!(value, Some(&(k * 10)));
+            assert!(!(pending.contains_key(&k) && done.contains_key(&k)));
+        }
+    };
+
+    for _ in 0..100 {
+        check();
+    }
+
+    stop.store(true, Ordering::SeqCst);
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    check();
+}
@@ -0,0 +1,311 @@
+use std::collections::HashSet;
+use stm::atomically;
+use stm_datastructures::THashSet;
+
+#[test]
+fn insert_contains_remove() {
+    let set = THashSet::new(4);
+
+    assert!(atomically(|trans| set.insert(trans, "a".to_string())));
+    assert!(!atomically(|trans| set.insert(trans, "a".to_string())));
+    assert!(atomically(|trans| set.contains(trans, "a")));
+    assert!(!atomically(|trans| set.contains(trans, "b")));
+    assert_eq!(atomically(|trans| set.len(trans)), 1);
+
+    assert!(atomically(|trans| set.remove(trans, "a")));
+    assert!(!atomically(|trans| set.remove(trans, "a")));
+    assert!(atomically(|trans| set.is_empty(trans)));
+}
+
+#[test]
+fn take_and_clear() {
+    let set = THashSet::new(3);
+    atomically(|trans| {
+        for i in 0..10 {
+            set.insert(trans, i)?;
+        }
+        Ok(())
+    });
+
+    assert_eq!(atomically(|trans| set.take(trans, &4)), Some(4));
+    assert_eq!(atomically(|trans| set.take(trans, &4)), None);
+    assert_eq!(atomically(|trans| set.len(trans)), 9);
+
+    atomically(|trans| set.clear(trans));
+    assert!(atomically(|trans| set.is_empty(trans)));
+}
+
+#[test]
+fn drain_empties_the_set() {
+    let set = THashSet::new(4);
+    atomically(|trans| {
+        for i in 0..20 {
+            set.insert(trans, i)?;
+        }
+        Ok(())
+    });
+
+    assert_eq!(atomically(|trans| set.to_vec(trans)).len(), 20);
+    assert_eq!(atomically(|trans| set.len(trans)), 20);
+
+    let mut drained = atomically(|trans| set.drain(trans));
+    drained.sort_unstable();
+    assert_eq!(drained, (0..20).collect::<Vec<_>>());
+    assert!(atomically(|trans| set.is_empty(trans)));
+    assert!(atomically(|trans| set.drain(trans)).is_empty());
+}
+
+#[test]
+fn custom_hasher() {
+    use stm_datastructures::FixedState;
+
+    let set = THashSet::with_buckets_and_hasher(4, FixedState::with_seed(42));
+    assert_eq!(set.hasher().seed(), 42);
+
+    atomically(|trans| set.insert(trans, 1u64));
+    assert!(atomically(|trans| set.contains(trans, &1)));
+}
+
+fn set_of<S>(set: &THashSet<u32, S>, values: std::ops::Range<u32>)
+where
+    S: std::hash::BuildHasher,
+{
+    atomically(|trans| {
+        for v in values.clone() {
+            set.insert(trans, v)?;
+        }
+        Ok(())
+    });
+}
+
+fn sorted(set: std::collections::HashSet<u32>) -> Vec<u32> {
+    let mut values: Vec<_> = set.into_iter().collect();
+    values.sort_unstable();
+    values
+}
+
+#[test]
+fn set_algebra() {
+    use stm_datastructures::FixedState;
+
+    let a = THashSet::new(4);
+    // one partner with the same layout and one with a different one
+    let b = THashSet::with_buckets_and_hasher(4, a.hasher().clone());
+    let c = THashSet::with_buckets_and_hasher(7, FixedState::with_seed(9));
+    set_of(&a, 0..10);
+    set_of(&b, 5..15);
+    set_of(&c, 5..15);
+
+    for (name, union, inter, diff, sym) in [
+        (
+            "same layout",
+            atomically(|trans| a.union(trans, &b)),
+            atomically(|trans| a.intersection(trans, &b)),
+            atomically(|trans| a.difference(trans, &b)),
+            atomically(|trans| a.symmetric_difference(trans, &b)),
+        ),
+        (
+            "different layout",
+            atomically(|trans| a.union(trans, &c)),
+            atomically(|trans| a.intersection(trans, &c)),
+            atomically(|trans| a.difference(trans, &c)),
+            atomically(|trans| a.symmetric_difference(trans, &c)),
+        ),
+    ] {
+        assert_eq!(sorted(union), (0..15).collect::<Vec<_>>(), "{}", name);
+        assert_eq!(sorted(inter), (5..10).collect::<Vec<_>>(), "{}", name);
+        assert_eq!(sorted(diff), (0..5).collect::<Vec<_>>(), "{}", name);
+        assert_eq!(
+            sorted(sym),
+            (0..5).chain(10..15).collect::<Vec<_>>(),
+            "{}",
+            name
+        );
+    }
+
+    assert!(!atomically(|trans| a.is_subset(trans, &c)));
+    assert!(!atomically(|trans| a.is_disjoint(trans, &c)));
+
+    let small = THashSet::new(2);
+    set_of(&small, 6..8);
+    assert!(atomically(|trans| small.is_subset(trans, &a)));
+    assert!(atomically(|trans| a.is_superset(trans, &small)));
+
+    let far = THashSet::new(3);
+    set_of(&far, 100..110);
+    assert!(atomically(|trans| a.is_disjoint(trans, &far)));
+}
+
+/// Hashes `u64`s to themselves and mixes other input bytes with `SEED`.
+#[derive(Clone, Default)]
+struct ByteSeeded<const SEED: u64>;
+
+impl<const SEED: u64> std::hash::BuildHasher for ByteSeeded<SEED> {
+    type Hasher = ByteSeededHasher;
+
+    fn build_hasher(&self) -> ByteSeededHasher {
+        ByteSeededHasher(SEED)
+    }
+}
+
+struct ByteSeededHasher(u64);
+
+impl std::hash::Hasher for ByteSeededHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = self.0.wrapping_mul(31).wrapping_add(*byte as u64);
+        }
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.0 = i;
+    }
+}
+
+#[test]
+fn set_algebra_with_hashers_agreeing_on_integers() {
+    let a = THashSet::with_buckets_and_hasher(8, ByteSeeded::<1>);
+    let b = THashSet::with_buckets_and_hasher(8, ByteSeeded::<2>);
+    atomically(|trans| {
+        for i in 0..50 {
+            a.insert(trans, i.to_string())?;
+            b.insert(trans, i.to_string())?;
+        }
+        Ok(())
+    });
+
+    assert_eq!(atomically(|trans| a.intersection(trans, &b)).len(), 50);
+    assert!(atomically(|trans| a.is_subset(trans, &b)));
+    assert!(atomically(|trans| a.difference(trans, &b)).is_empty());
+}
+
+#[test]
+fn set_algebra_with_deep_clones() {
+    let a = THashSet::new(4);
+    set_of(&a, 0..10);
+    let b = atomically(|trans| a.deep_clone(trans));
+    atomically(|trans| {
+        for v in 0..5 {
+            b.remove(trans, &v)?;
+        }
+        Ok(())
+    });
+    set_of(&b, 10..15);
+
+    assert_eq!(sorted(atomically(|trans| a.intersection(trans, &b))), (5..10).collect::<Vec<_>>());
+    assert_eq!(sorted(atomically(|trans| b.difference(trans, &a))), (10..15).collect::<Vec<_>>());
+    assert!(atomically(|trans| a.is_subset(trans, &a.handle())));
+
+    // a deep clone keeps the hasher, but no longer the layout once it is resized
+    atomically(|trans| b.resize(trans, 9));
+    assert_eq!(
+        sorted(atomically(|trans| a.symmetric_difference(trans, &b))),
+        (0..5).chain(10..15).collect::<Vec<_>>()
+    );
+
+    atomically(|trans| a.resize(trans, 9));
+    atomically(|trans| a.extend_from(trans, &b));
+    atomically(|trans| b.retain_in(trans, &a));
+    assert_eq!(
+        sorted(atomically(|trans| a.to_vec(trans)).into_iter().collect()),
+        (0..15).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        sorted(atomically(|trans| b.to_vec(trans)).into_iter().collect()),
+        (5..15).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn set_algebra_in_place() {
+    use stm_datastructures::FixedState;
+
+    let a = THashSet::new(4);
+    let b = THashSet::with_buckets_and_hasher(5, FixedState::with_seed(1));
+    set_of(&a, 0..10);
+    set_of(&b, 5..15);
+
+    atomically(|trans| a.extend_from(trans, &b));
+    assert_eq!(atomically(|trans| a.len(trans)), 15);
+
+    let c = THashSet::new(2);
+    set_of(&c, 0..7);
+    atomically(|trans| b.retain_in(trans, &c));
+    assert_eq!(sorted(atomically(|trans| b.to_vec(trans)).into_iter().collect()), vec![5, 6]);
+}
+
+#[test]
+fn retain_and_drain_filter() {
+    let set = THashSet::new(4);
+    set_of(&set, 0..20);
+
+    let mut odd = atomically(|trans| set.drain_filter(trans, |v| v % 2 == 1));
+    odd.sort_unstable();
+    assert_eq!(odd, (0..20).filter(|v| v % 2 == 1).collect::<Vec<_>>());
+
+    atomically(|trans| set.retain(trans, |v| *v < 10));
+    assert_eq!(
+        sorted(atomically(|trans| set.to_vec(trans)).into_iter().collect()),
+        vec![0, 2, 4, 6, 8]
+    );
+}
+
+#[test]
+fn map_values_moves_changed_values() {
+    let set = THashSet::new(4);
+    set_of(&set, 0..20);
+
+    // every value is mapped at most once, and values mapped onto each other are merged
+    atomically(|trans| {
+        set.map_values_in_place(trans, |v| if *v < 10 { Some(v + 100) } else { Some(v / 2) })
+    });
+    assert_eq!(
+        sorted(atomically(|trans| set.to_vec(trans)).into_iter().collect()),
+        (5..10).chain(100..110).collect::<Vec<_>>()
+    );
+    assert_eq!(atomically(|trans| set.len(trans)), 15);
+    assert!(atomically(|trans| set.contains(trans, &105)));
+    assert!(!atomically(|trans| set.contains(trans, &15)));
+}
+
+#[test]
+fn iterator() {
+    let set = THashSet::new(3);
+    set_of(&set, 0..10);
+
+    let values: Vec<u32> = atomically(|trans| set.iter(trans).collect());
+    assert_eq!(sorted(values.into_iter().collect()), (0..10).collect::<Vec<_>>());
+
+    assert!(atomically(|trans| Ok(set.iter(trans).any(|v| v == Ok(5)))));
+}
+
+#[test]
+fn std_conversions() {
+    let set: THashSet<u32> = (0..50).collect();
+    assert_eq!(atomically(|trans| set.len(trans)), 50);
+
+    let mut set = THashSet::<u32>::from(vec![1, 2, 2, 3]);
+    set.extend(vec![3, 4]);
+    assert_eq!(sorted(set.clone().into_iter().collect()), vec![1, 2, 3, 4]);
+
+    let source: HashSet<u32> = (10..20).collect();
+    let set = THashSet::<u32>::from(source.clone());
+    assert_eq!(set.into_iter().collect::<HashSet<_>>(), source);
+}
+
+#[test]
+fn handle_and_deep_clone() {
+    let set = THashSet::new(4);
+    let handle = set.handle();
+    atomically(|trans| set.insert(trans, 1u32));
+
+    let copy = atomically(|trans| handle.deep_clone(trans));
+    atomically(|trans| copy.insert(trans, 2));
+
+    assert_eq!(sorted(set.into_iter().collect()), vec![1]);
+    assert_eq!(sorted(copy.into_iter().collect()), vec![1, 2]);
+}
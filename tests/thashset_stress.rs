@@ -0,0 +1,104 @@
+//! Stress tests checking that `THashSet` operations are serializable under contention.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use stm::atomically;
+use stm_datastructures::THashSet;
+
+const THREADS: usize = 16;
+const VALUES: usize = 2_000;
+
+/// Every thread tries to insert the same values. Exactly one insertion per value may report
+/// that the value was new.
+#[test]
+fn concurrent_insert_reports_each_value_once() {
+    let set = Arc::new(THashSet::new(8));
+    let new_count = Arc::new(AtomicUsize::new(0));
+
+    let handles: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let set = Arc::clone(&set);
+            let new_count = Arc::clone(&new_count);
+            thread::spawn(move || {
+                for value in 0..VALUES {
+                    if atomically(|trans| set.insert(trans, value)) {
+                        new_count.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(new_count.load(Ordering::SeqCst), VALUES);
+    assert_eq!(atomically(|trans| set.to_vec(trans)).len(), VALUES);
+}
+
+/// With a single bucket every insertion contends on the same `TVar`, which maximizes the window
+/// for a stale membership check.
+#[test]
+fn concurrent_insert_single_bucket() {
+    let set = Arc::new(THashSet::new(1));
+    let new_count = Arc::new(AtomicUsize::new(0));
+
+    let handles: Vec<_> = (0..THREADS)
+        .map(|thread_no| {
+            let set = Arc::clone(&set);
+            let new_count = Arc::clone(&new_count);
+            thread::spawn(move || {
+                // start at different offsets so threads collide at different times
+                for i in 0..VALUES {
+                    let value = (i + thread_no * 37) % VALUES;
+                    if atomically(|trans| set.insert(trans, value)) {
+                        new_count.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(new_count.load(Ordering::SeqCst), VALUES);
+}
+
+/// Inserting several values in one transaction must report a consistent result for all of them.
+#[test]
+fn concurrent_multi_value_transactions() {
+    let set = Arc::new(THashSet::new(4));
+    let new_count = Arc::new(AtomicUsize::new(0));
+
+    let handles: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let set = Arc::clone(&set);
+            let new_count = Arc::clone(&new_count);
+            thread::spawn(move || {
+                for chunk in 0..VALUES / 4 {
+                    let inserted = atomically(|trans| {
+                        let mut inserted = 0;
+                        for value in chunk * 4..(chunk + 1) * 4 {
+                            if set.insert(trans, value)? {
+                                inserted += 1;
+                            }
+                        }
+                        Ok(inserted)
+                    });
+                    new_count.fetch_add(inserted, Ordering::SeqCst);
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(new_count.load(Ordering::SeqCst), VALUES);
+}